@@ -1,33 +1,106 @@
 // Copyright 2021 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io::Read;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
+use semver::Version;
 use toml::value::Table;
 use toml::Value;
 
-use super::{DictEdit, DictEditAction, ListEdit, ListEditAction, OptionsSource, Val};
+use super::{BuildRoot, DictEdit, DictEditAction, ListEdit, ListEditAction, OptionsSource, Val};
 use crate::fromfile::FromfileExpander;
-use crate::id::{NameTransform, OptionId};
-use crate::parse::Parseable;
+use crate::id::{NameTransform, OptionId, Scope};
+use crate::parse::{eval_arithmetic, parse_int_ranges, Parseable};
+use crate::render_choice;
 
 type InterpolationMap = HashMap<String, String>;
 
+type WarningSink = Arc<dyn Fn(Severity, &str) + Send + Sync>;
+
 static DEFAULT_SECTION: &str = "DEFAULT";
 
+// The top-level key (outside of any section) that marks the version of the config schema that a
+// config file was written for.
+static CONFIG_VERSION_KEY: &str = "config_version";
+
+/// The newest version of the config schema that this crate supports.
+pub const CONFIG_VERSION: i64 = 1;
+
+// The section that lists the options that configs merged on top of a config may not override.
+static LOCKED_SECTION: &str = "_locked";
+
+// The key of a section that names the scope whose options it falls back to.
+static INHERITS_KEY: &str = "_inherits";
+
+// An inline array written on a line longer than this is hard to read and to diff.
+const MAX_INLINE_ARRAY_LINE_LEN: usize = 100;
+// Inline tables nested deeper than this are better written as their own sections.
+const MAX_INLINE_TABLE_DEPTH: usize = 2;
+
+// The platforms that a scalar option may be given a value for, by a table such as
+// `{linux = "...", macos = "..."}`, along with the `default` for any other platform.
+static PLATFORM_KEYS: &[&str] = &["linux", "macos", "windows", "default"];
+
+// Sections whose names have a special meaning, rather than naming an ordinary scope.
+static RESERVED_SECTIONS: &[&str] = &["DEFAULT", "GLOBAL", LOCKED_SECTION];
+
 lazy_static! {
     static ref PLACEHOLDER_RE: Regex = Regex::new(r"%\(([a-zA-Z0-9_.]+)\)s").unwrap();
+    static ref DICT_REFERENCE_RE: Regex = Regex::new(r"%\(\.([a-zA-Z0-9_]+)\)s").unwrap();
+    static ref ENV_VAR_NAME_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    static ref FILE_REF_RE: Regex = Regex::new(r"\$\{([^:{}]+\.toml):([^{}]+)\}").unwrap();
+    static ref ANNOTATION_RE: Regex = Regex::new(r"^#\s*@pants:\s*([a-z_]+)\s*(.*?)\s*$").unwrap();
+    static ref SECTION_HEADER_RE: Regex = Regex::new(r#"^\[\s*"?([^"\[\]]+?)"?\s*\]"#).unwrap();
+    static ref OPTION_KEY_RE: Regex = Regex::new(r#"^"?([a-zA-Z0-9_-]+)"?\s*[.=]"#).unwrap();
 }
 
+// The placeholders that may always be interpolated, as `%(user)s` and `%(home)s`. A seed value
+// of the same name takes precedence, which lets embedders (and tests) pin them.
+fn builtin_placeholders() -> InterpolationMap {
+    HashMap::from([
+        ("user".to_owned(), whoami::username()),
+        ("home".to_owned(), shellexpand::tilde("~").into_owned()),
+    ])
+}
+
+// Interpolates the `%(name)s` placeholders in `value`. If `env_allowlist` is given, errors for
+// any `%(env.X)s` placeholder for an env var X that it doesn't contain. A replacement may itself
+// contain placeholders, but errors if it refers back to a placeholder being interpolated.
+//
+// A `%(.KEY)s` placeholder references another value of the same dict, so is left as is, to be
+// resolved by `ConfigReader::get_string_dict`.
 pub(crate) fn interpolate_string(
     value: String,
     replacements: &InterpolationMap,
+    env_allowlist: Option<&HashSet<String>>,
+) -> Result<String, String> {
+    interpolate_placeholders(value, replacements, env_allowlist, &mut vec![])
+}
+
+// Interpolates `value` as for `interpolate_string`, where `expanding` holds the names of the
+// placeholders whose replacements are being interpolated, outermost first.
+fn interpolate_placeholders(
+    value: String,
+    replacements: &InterpolationMap,
+    env_allowlist: Option<&HashSet<String>>,
+    expanding: &mut Vec<String>,
 ) -> Result<String, String> {
-    let caps_vec: Vec<_> = PLACEHOLDER_RE.captures_iter(&value).collect();
+    let caps_vec: Vec<_> = PLACEHOLDER_RE
+        .captures_iter(&value)
+        .filter(|caps| !caps[1].starts_with('.'))
+        .collect();
     if caps_vec.is_empty() {
         return Ok(value);
     }
@@ -38,16 +111,38 @@ pub(crate) fn interpolate_string(
         let m = caps.get(0).unwrap();
         new_value.push_str(&value[last_match..m.start()]);
         let placeholder_name = &caps[1];
+        if let (Some(env_var), Some(env_allowlist)) =
+            (placeholder_name.strip_prefix("env."), env_allowlist)
+        {
+            if !env_allowlist.contains(env_var) {
+                return Err(format!(
+                    "Interpolation of env var {env_var} is not permitted"
+                ));
+            }
+        }
+        if let Some(start) = expanding.iter().position(|name| name == placeholder_name) {
+            return Err(format!(
+                "Placeholders refer to each other in a cycle: {} -> {placeholder_name}",
+                expanding[start..].join(" -> ")
+            ));
+        }
         let replacement = replacements.get(placeholder_name).ok_or(format!(
             "Unknown value for placeholder `{}`",
             placeholder_name
         ))?;
-        new_value.push_str(replacement);
+        expanding.push(placeholder_name.to_owned());
+        let replacement = interpolate_placeholders(
+            replacement.to_owned(),
+            replacements,
+            env_allowlist,
+            expanding,
+        )?;
+        expanding.pop();
+        new_value.push_str(&replacement);
         last_match = m.end();
     }
     new_value.push_str(&value[last_match..]);
-    // A replacement string may itself contain a placeholder, so we recurse.
-    interpolate_string(new_value, replacements)
+    Ok(new_value)
 }
 
 struct InterpolationError {
@@ -59,18 +154,21 @@ fn interpolate_value(
     key: &str,
     value: Value,
     replacements: &InterpolationMap,
+    env_allowlist: Option<&HashSet<String>>,
 ) -> Result<Value, InterpolationError> {
     Ok(match value {
-        Value::String(s) => Value::String(interpolate_string(s, replacements).map_err(|msg| {
-            InterpolationError {
-                key: key.to_string(),
-                msg,
-            }
-        })?),
+        Value::String(s) => Value::String(
+            interpolate_string(s, replacements, env_allowlist).map_err(|msg| {
+                InterpolationError {
+                    key: key.to_string(),
+                    msg,
+                }
+            })?,
+        ),
         Value::Array(v) => {
             let new_v: Result<Vec<_>, _> = v
                 .into_iter()
-                .map(|x| interpolate_value(key, x, replacements))
+                .map(|x| interpolate_value(key, x, replacements, env_allowlist))
                 .collect();
             Value::Array(new_v?)
         }
@@ -83,6 +181,7 @@ fn interpolate_value(
                         if key.is_empty() { &k } else { key },
                         v,
                         replacements,
+                        env_allowlist,
                     ) {
                         Ok(new_v) => Ok((k, new_v)),
                         Err(s) => Err(s),
@@ -95,6 +194,366 @@ fn interpolate_value(
     })
 }
 
+// The state of resolving `${path:scope.option}` references while parsing a config file: the files
+// currently being parsed, in order to detect cycles, and those already parsed, since a file may
+// be referenced many times. Any allowlist of the env vars that interpolation may read applies
+// to referenced files as well.
+#[derive(Default)]
+struct FileRefs {
+    parsing: Vec<PathBuf>,
+    parsed: HashMap<PathBuf, Config>,
+    env_allowlist: Option<HashSet<String>>,
+}
+
+impl FileRefs {
+    // Resolves each `${path:scope.option}` reference in the string values within `value`. A
+    // string that consists of just a reference takes on the referenced value, whatever its type.
+    fn resolve_value(
+        &mut self,
+        key: &str,
+        value: Value,
+        config_dir: &Path,
+        seed_values: &InterpolationMap,
+    ) -> Result<Value, InterpolationError> {
+        let error = |msg| InterpolationError {
+            key: key.to_string(),
+            msg,
+        };
+        Ok(match value {
+            Value::String(s) => {
+                let caps_vec: Vec<_> = FILE_REF_RE.captures_iter(&s).collect();
+                if caps_vec.len() == 1 && caps_vec[0][0].len() == s.len() {
+                    self.referenced_value(&caps_vec[0], config_dir, seed_values)
+                        .map_err(error)?
+                } else {
+                    let mut new_value = String::with_capacity(s.len());
+                    let mut last_match = 0;
+                    for caps in &caps_vec {
+                        let m = caps.get(0).unwrap();
+                        new_value.push_str(&s[last_match..m.start()]);
+                        match self
+                            .referenced_value(caps, config_dir, seed_values)
+                            .map_err(error)?
+                        {
+                            Value::String(referenced) => new_value.push_str(&referenced),
+                            referenced => new_value.push_str(&referenced.to_string()),
+                        }
+                        last_match = m.end();
+                    }
+                    new_value.push_str(&s[last_match..]);
+                    Value::String(new_value)
+                }
+            }
+            Value::Array(v) => Value::Array(
+                v.into_iter()
+                    .map(|x| self.resolve_value(key, x, config_dir, seed_values))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Table(t) => Value::Table(
+                t.into_iter()
+                    .map(|(k, v)| {
+                        // Use the section-level key even if this is a nested table value.
+                        let key = if key.is_empty() { &k } else { key };
+                        let new_v = self.resolve_value(key, v, config_dir, seed_values)?;
+                        Ok((k, new_v))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => value,
+        })
+    }
+
+    fn referenced_value(
+        &mut self,
+        caps: &regex::Captures,
+        config_dir: &Path,
+        seed_values: &InterpolationMap,
+    ) -> Result<Value, String> {
+        let reference = &caps[0];
+        let (scope, option_name) = caps[2].rsplit_once('.').ok_or_else(|| {
+            format!("Expected a reference of the form ${{path:scope.option}} but given {reference}")
+        })?;
+        let path = config_dir.join(&caps[1]);
+        let path = fs::canonicalize(&path).map_err(|_| {
+            format!(
+                "File {} referenced by {reference} does not exist",
+                path.display()
+            )
+        })?;
+        if let Some(index) = self.parsing.iter().position(|parsing| *parsing == path) {
+            let cycle = self.parsing[index..]
+                .iter()
+                .chain([&path])
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "Cycle in references between config files: {}",
+                cycle.join(" -> ")
+            ));
+        }
+        let lookup = |config: &Config| {
+            if config.value.get(scope).is_none() {
+                return Err(format!(
+                    "File {} referenced by {reference} has no [{scope}] section",
+                    path.display()
+                ));
+            }
+            config.get(scope, option_name).cloned().ok_or_else(|| {
+                format!(
+                    "Section [{scope}] of file {} referenced by {reference} has no option \
+                    {option_name}",
+                    path.display()
+                )
+            })
+        };
+        if let Some(config) = self.parsed.get(&path) {
+            return lookup(config);
+        }
+        let config =
+            Config::parse_with_file_refs(&ConfigSource::from_file(&path)?, seed_values, self)?;
+        let value = lookup(&config);
+        self.parsed.insert(path.clone(), config);
+        value
+    }
+}
+
+// Parses the `[_locked]` section, whose `options` list names each locked option as
+// `scope.option`, into the section and option name of each.
+fn parse_locked_options(section: &Value) -> Result<HashSet<(String, String)>, String> {
+    let expected = || {
+        format!(
+            "Expected [{LOCKED_SECTION}] to contain just an `options` list of `scope.option` \
+            strings"
+        )
+    };
+    let table = section.as_table().filter(|table| table.len() == 1);
+    let options = table
+        .and_then(|table| table.get("options"))
+        .and_then(Value::as_array)
+        .ok_or_else(expected)?;
+    options
+        .iter()
+        .map(|option| {
+            option
+                .as_str()
+                .and_then(|option| option.rsplit_once('.'))
+                .map(|(scope, option_name)| (scope.to_owned(), option_name.to_owned()))
+                .ok_or_else(expected)
+        })
+        .collect()
+}
+
+// Removes the `_inherits` key from each section, returning the scope that each section inherits
+// from.
+fn take_inherits(sections: &mut Table) -> Result<HashMap<String, String>, String> {
+    let mut inherits = HashMap::new();
+    for (section_name, section) in sections.iter_mut() {
+        if let Some(parent) = section
+            .as_table_mut()
+            .and_then(|table| table.remove(INHERITS_KEY))
+        {
+            match parent {
+                Value::String(parent) => {
+                    inherits.insert(section_name.clone(), parent);
+                }
+                value => {
+                    return Err(format!(
+                        "Expected {INHERITS_KEY} in section [{section_name}] to be the name of a \
+                        scope but given {value}"
+                    ))
+                }
+            }
+        }
+    }
+    Ok(inherits)
+}
+
+// Returns the scopes of a cycle in `inherits`, starting and ending with the same scope, if there
+// is such a cycle.
+fn inheritance_cycle(inherits: &HashMap<String, String>) -> Option<Vec<&str>> {
+    for child in inherits.keys().sorted() {
+        let mut chain = vec![child.as_str()];
+        while let Some(parent) = inherits.get(*chain.last().unwrap()) {
+            if let Some(start) = chain.iter().position(|scope| scope == parent) {
+                chain.push(parent);
+                return Some(chain.split_off(start));
+            }
+            chain.push(parent);
+        }
+    }
+    None
+}
+
+// Resolves the `%(.KEY)s` references between the string values of a dict, where each references
+// the value of KEY in the same dict or, failing that, in `base`. Values are resolved in
+// dependency order, so that a referenced value has its own references resolved first. A value
+// that references its own key references the value of that key in `base`, which it replaces.
+struct DictReferenceResolver<'a> {
+    id: &'a OptionId,
+    items: &'a HashMap<String, Val>,
+    base: &'a HashMap<String, Val>,
+    resolved: HashMap<String, String>,
+    // The keys whose values are being resolved, in the order that they were referenced.
+    resolving: Vec<String>,
+}
+
+impl<'a> DictReferenceResolver<'a> {
+    fn resolve(&mut self, key: &str) -> Result<String, String> {
+        if let Some(value) = self.resolved.get(key) {
+            return Ok(value.clone());
+        }
+        if let Some(start) = self.resolving.iter().position(|k| k == key) {
+            let cycle = self.resolving[start..]
+                .iter()
+                .map(String::as_str)
+                .chain([key])
+                .join(" -> ");
+            return Err(format!(
+                "Values of {} reference each other in a cycle: {cycle}",
+                self.id
+            ));
+        }
+        let value = match self.items.get(key).or_else(|| self.base.get(key)) {
+            Some(value) => self.string_value(key, value)?,
+            None => return Err(format!("Unknown key `{key}` referenced in {}", self.id)),
+        };
+
+        self.resolving.push(key.to_owned());
+        let mut resolved_value = String::with_capacity(value.len());
+        let mut last_match = 0;
+        for caps in DICT_REFERENCE_RE.captures_iter(value) {
+            let m = caps.get(0).unwrap();
+            resolved_value.push_str(&value[last_match..m.start()]);
+            if &caps[1] == key {
+                // The values of `base` were resolved by their own edits.
+                let base_value = self.base.get(key).ok_or_else(|| {
+                    format!(
+                        "The value of `{key}` in {} references its earlier value, but no \
+                        earlier edit sets it",
+                        self.id
+                    )
+                })?;
+                resolved_value.push_str(self.string_value(key, base_value)?);
+            } else {
+                resolved_value.push_str(&self.resolve(&caps[1])?);
+            }
+            last_match = m.end();
+        }
+        resolved_value.push_str(&value[last_match..]);
+        self.resolving.pop();
+
+        self.resolved.insert(key.to_owned(), resolved_value.clone());
+        Ok(resolved_value)
+    }
+
+    // Returns `value`, the value of `key`, if it is a string, since only strings may be referenced.
+    fn string_value<'v>(&self, key: &str, value: &'v Val) -> Result<&'v str, String> {
+        match value {
+            Val::String(value) => Ok(value),
+            value => Err(format!(
+                "Expected the value of `{key}` in {} to be a string, as it is referenced by \
+                another value, but given {value:?}",
+                self.id
+            )),
+        }
+    }
+}
+
+// A scalar value of the form `{from_env = "VAR_NAME"}` is read from the named env var, which keeps
+// the value itself (typically a secret) out of the config file.
+fn from_env_var_name(value: &Value) -> Option<&str> {
+    match value {
+        Value::Table(table) if table.len() == 1 => table.get("from_env").and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+// A scalar value of the form `{from_command = "COMMAND"}` is the trimmed stdout of running the
+// command, if the embedder has allowed commands to be run (see `ConfigReader::allow_commands`).
+fn from_command_line(value: &Value) -> Option<&str> {
+    match value {
+        Value::Table(table) if table.len() == 1 => {
+            table.get("from_command").and_then(Value::as_str)
+        }
+        _ => None,
+    }
+}
+
+// A numeric value of the form `{expr = "4 * num_cores"}` is the result of evaluating the arithmetic
+// expression over the variables that the embedder provides (see `ConfigReader::with_variables`).
+fn arithmetic_expr(value: &Value) -> Option<&str> {
+    match value {
+        Value::Table(table) if table.len() == 1 => table.get("expr").and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+// A scalar value of the form `{lookup = "KEY", table = "TABLE"}` is the value of the key in the
+// named lookup table that the embedder provides (see `ConfigReader::with_lookup_tables`).
+fn lookup_key(value: &Value) -> Option<(&str, &str)> {
+    match value {
+        Value::Table(table) if table.len() == 2 => table
+            .get("lookup")
+            .and_then(Value::as_str)
+            .zip(table.get("table").and_then(Value::as_str)),
+        _ => None,
+    }
+}
+
+// Runs `command_line` via the platform shell, returning its trimmed stdout if it exits
+// successfully within `timeout`, and killing it otherwise.
+fn run_command(command_line: &str, timeout: Duration) -> Result<String, String> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    let mut child = command
+        .arg(command_line)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to run `{command_line}`: {e}"))?;
+    // Read stdout on another thread, so that a chatty command can't block on a full pipe while
+    // we wait for it to exit.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|e| format!("Failed to run `{command_line}`: {e}"))?
+        {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "Command `{command_line}` timed out after {}ms",
+                timeout.as_millis()
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        return Err(format!("Command `{command_line}` failed with {status}"));
+    }
+    let output = reader
+        .join()
+        .map_err(|_| format!("Failed to read the output of `{command_line}`"))?
+        .map_err(|e| format!("Failed to read the output of `{command_line}`: {e}"))?;
+    Ok(output.trim().to_owned())
+}
+
 struct ValueConversionError<'a> {
     expected_type: &'static str,
     given_value: &'a Value,
@@ -104,8 +563,44 @@ trait FromValue: Parseable {
     fn from_value(value: &Value) -> Result<Self, ValueConversionError>;
 
     fn from_config(config: &ConfigReader, id: &OptionId) -> Result<Option<Self>, String> {
-        if let Some(value) = config.get_value(id) {
-            if value.is_str() {
+        let value = match config.get_value(id) {
+            Some(value) => config.platform_value(id, value)?,
+            None => None,
+        };
+        if let Some(value) = value {
+            if let Some(env_var_name) = from_env_var_name(value) {
+                let env_value = config.config.env.get(env_var_name).ok_or_else(|| {
+                    format!("Expected env var {env_var_name} to be set for {id} (via from_env)")
+                })?;
+                Ok(Some(
+                    Self::parse(env_value).map_err(|e| e.render(config.display(id)))?,
+                ))
+            } else if let Some(command_line) = from_command_line(value) {
+                let timeout = config.command_timeout.ok_or_else(|| {
+                    format!(
+                        "Cannot run `{command_line}` for {id} (via from_command): running \
+                        commands from config is not enabled"
+                    )
+                })?;
+                let output = run_command(command_line, timeout)
+                    .map_err(|e| format!("{e} for {id} (via from_command)"))?;
+                Ok(Some(
+                    Self::parse(&output).map_err(|e| e.render(config.display(id)))?,
+                ))
+            } else if let Some((key, table_name)) = lookup_key(value) {
+                let table = config.lookup_tables.get(table_name).ok_or_else(|| {
+                    format!("Unknown lookup table `{table_name}` for {id} (via lookup)")
+                })?;
+                let looked_up_value = table.get(key).ok_or_else(|| {
+                    format!(
+                        "Expected lookup table `{table_name}` to contain the key `{key}` for {id} \
+                        (via lookup)"
+                    )
+                })?;
+                Ok(Some(
+                    Self::parse(looked_up_value).map_err(|e| e.render(config.display(id)))?,
+                ))
+            } else if value.is_str() {
                 match config
                     .fromfile_expander
                     .expand(value.as_str().unwrap().to_owned())
@@ -201,6 +696,337 @@ impl FromValue for f64 {
     }
 }
 
+///
+/// Expands shell-style brace patterns in `pattern`, so that `py3{9,10}` becomes `py39` and
+/// `py310`. Groups may be nested, and a backslash escapes the character following it.
+///
+/// As in the shell, a group without any top-level comma (such as `{a}`) is kept literally.
+///
+fn expand_braces(pattern: &str) -> Result<Vec<String>, String> {
+    let chars = pattern.chars().collect::<Vec<_>>();
+    let mut pos = 0;
+    let (expansions, _) = expand_brace_alternatives(&chars, &mut pos, false)
+        .map_err(|e| format!("{e} in brace pattern {pattern}"))?;
+    Ok(expansions)
+}
+
+// Expands the comma-separated alternatives starting at `pos`, up to and including the `}` that
+// closes the group when `in_group`, or to the end of the input otherwise. Returns the expansions
+// along with whether more than one alternative was found.
+fn expand_brace_alternatives(
+    chars: &[char],
+    pos: &mut usize,
+    in_group: bool,
+) -> Result<(Vec<String>, bool), String> {
+    let mut expansions = vec![];
+    let mut current = vec![String::new()];
+    let mut alternated = false;
+    while let Some(&c) = chars.get(*pos) {
+        *pos += 1;
+        match c {
+            '{' => {
+                let (group, group_alternated) = expand_brace_alternatives(chars, pos, true)?;
+                let group = if group_alternated {
+                    group
+                } else {
+                    group.iter().map(|item| format!("{{{item}}}")).collect()
+                };
+                current = current
+                    .iter()
+                    .flat_map(|prefix| group.iter().map(move |suffix| format!("{prefix}{suffix}")))
+                    .collect();
+            }
+            ',' if in_group => {
+                expansions.extend(std::mem::replace(&mut current, vec![String::new()]));
+                alternated = true;
+            }
+            '}' if in_group => {
+                expansions.extend(current);
+                return Ok((expansions, alternated));
+            }
+            '}' => return Err("Unmatched '}'".to_owned()),
+            c => {
+                let literal = if c == '\\' && *pos < chars.len() {
+                    *pos += 1;
+                    chars[*pos - 1]
+                } else {
+                    c
+                };
+                for prefix in current.iter_mut() {
+                    prefix.push(literal);
+                }
+            }
+        }
+    }
+    if in_group {
+        return Err("Unmatched '{'".to_owned());
+    }
+    expansions.extend(current);
+    Ok((expansions, alternated))
+}
+
+// The Levenshtein distance between `a` and `b`, for suggesting corrections to typos.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut distances = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut previous_diagonal = distances[0];
+        distances[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous_diagonal + usize::from(a_char != *b_char);
+            previous_diagonal = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(distances[j + 1] + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+// Applies `list_edits` in order to an empty list. As across sources, removals apply after all
+// adds, but are themselves discarded by any later replace or clear.
+fn resolve_list_edits<T: PartialEq>(list_edits: Vec<ListEdit<T>>) -> Vec<T> {
+    let mut list = vec![];
+    let mut removals = vec![];
+    for list_edit in list_edits {
+        match list_edit.action {
+            ListEditAction::Replace => {
+                list = list_edit.items;
+                removals.clear();
+            }
+            ListEditAction::Add => list.extend(list_edit.items),
+            ListEditAction::Remove => removals.extend(list_edit.items),
+            ListEditAction::Clear => {
+                list.clear();
+                removals.clear();
+            }
+        }
+    }
+    list.retain(|item| !removals.contains(item));
+    list
+}
+
+// Returns the list edits that the list option `value` stands for, if it is an array or a table of
+// `clear`, `from_file`, `add` and `remove` edits. A `from_file` list is read with
+// `fromfile_expander` as a string list, erroring for the option named `display` if it can't be.
+fn toml_list_edits(
+    value: &Value,
+    display: &str,
+    fromfile_expander: &FromfileExpander,
+) -> Result<Option<Vec<ListEdit<Value>>>, String> {
+    Ok(match value {
+        Value::Array(items) => Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: items.clone(),
+        }]),
+        Value::Table(edits) if is_list_edit_table(edits) => {
+            let mut list_edits = vec![];
+            if edits.get("clear").and_then(Value::as_bool) == Some(true) {
+                list_edits.push(ListEdit {
+                    action: ListEditAction::Clear,
+                    items: vec![],
+                });
+            }
+            if let Some(path) = edits.get("from_file").and_then(Value::as_str) {
+                let from_file_edits = fromfile_expander
+                    .expand_to_list::<String>(format!("@{path}"))
+                    .map_err(|e| e.render(display))?;
+                list_edits.extend(from_file_edits.into_iter().flatten().map(|list_edit| {
+                    ListEdit {
+                        action: list_edit.action,
+                        items: list_edit.items.into_iter().map(Value::String).collect(),
+                    }
+                }));
+            }
+            for (key, action) in [
+                ("add", ListEditAction::Add),
+                ("remove", ListEditAction::Remove),
+            ] {
+                if let Some(Value::Array(items)) = edits.get(key) {
+                    list_edits.push(ListEdit {
+                        action,
+                        items: items.clone(),
+                    });
+                }
+            }
+            Some(list_edits)
+        }
+        _ => None,
+    })
+}
+
+fn is_list_edit_table(table: &Table) -> bool {
+    !table.is_empty()
+        && table.iter().all(|(key, value)| match key.as_str() {
+            "add" | "remove" => value.is_array(),
+            "clear" => value.is_bool(),
+            "from_file" => value.is_str(),
+            _ => false,
+        })
+}
+
+// Returns the edit table that applies the list edits of `base` and then those of `overlay`, if
+// both are tables of list edits. An overlay that clears the list discards the edits of `base`.
+// There is no such table when `overlay` reads a `from_file` list, since that list would have to
+// apply after the adds and removes of `base`.
+fn concat_list_edit_tables(base: &Value, overlay: &Value) -> Option<Value> {
+    let (Value::Table(base), Value::Table(overlay)) = (base, overlay) else {
+        return None;
+    };
+    if !is_list_edit_table(base)
+        || !is_list_edit_table(overlay)
+        || overlay.contains_key("from_file")
+    {
+        return None;
+    }
+    if overlay.get("clear").and_then(Value::as_bool) == Some(true) {
+        return Some(Value::Table(overlay.clone()));
+    }
+    let mut edits = base.clone();
+    for key in ["add", "remove"] {
+        if let Some(Value::Array(items)) = overlay.get(key) {
+            match edits.get_mut(key) {
+                Some(Value::Array(existing)) => existing.extend(items.iter().cloned()),
+                _ => {
+                    edits.insert(key.to_owned(), Value::Array(items.clone()));
+                }
+            }
+        }
+    }
+    Some(Value::Table(edits))
+}
+
+// Returns whether the list edits that `base` makes, as a table or as a string such as `"+['a']"`,
+// are discarded when `overlay` replaces it as the value of an option. Edit tables are
+// concatenated rather than replaced, except for one that reads a `from_file` list.
+fn discards_list_edits(base: &Value, overlay: &Value) -> bool {
+    let base_edits = match base {
+        Value::Table(table) => is_list_edit_table(table),
+        Value::String(value) => ["+[", "-["]
+            .iter()
+            .any(|prefix| value.trim_start().starts_with(prefix)),
+        _ => false,
+    };
+    let replaces = match overlay {
+        Value::Array(_) => true,
+        Value::Table(table) => is_list_edit_table(table),
+        Value::String(value) => ["[", "+[", "-["]
+            .iter()
+            .any(|prefix| value.trim_start().starts_with(prefix)),
+        _ => false,
+    };
+    base_edits && replaces
+}
+
+// Returns the items that the dict option `value` adds, if it is of the form `{add = {...}}`.
+fn toml_dict_adds(value: &Value) -> Option<&Table> {
+    match value {
+        Value::Table(table) if table.len() == 1 => table.get("add").and_then(Value::as_table),
+        _ => None,
+    }
+}
+
+fn is_edit(value: &Value) -> bool {
+    matches!(value, Value::Table(table) if is_list_edit_table(table))
+        || toml_dict_adds(value).is_some()
+}
+
+// Resolves the non-empty `values` of the option named `display`, in the order that they apply, on
+// top of `base` into a single concrete value. Values that can't be resolved, such as a list edit
+// string, leave the last value as it is, but a `from_file` list that can't be read is an error.
+fn resolve_edit_values(
+    base: Option<&Value>,
+    values: &[&Value],
+    display: &str,
+    fromfile_expander: &FromfileExpander,
+) -> Result<Value, String> {
+    let last = values[values.len() - 1];
+    if values
+        .iter()
+        .any(|value| matches!(value, Value::Table(table) if is_list_edit_table(table)))
+    {
+        let list_edits = base
+            .filter(|base| base.is_array())
+            .into_iter()
+            .chain(values.iter().copied())
+            .map(|value| toml_list_edits(value, display, fromfile_expander))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .collect::<Option<Vec<_>>>();
+        if let Some(list_edits) = list_edits {
+            return Ok(Value::Array(resolve_list_edits(itertools::concat(
+                list_edits,
+            ))));
+        }
+    } else if values.iter().any(|value| toml_dict_adds(value).is_some()) {
+        let mut dict = base
+            .and_then(Value::as_table)
+            .cloned()
+            .unwrap_or_else(Table::new);
+        for value in values {
+            match (toml_dict_adds(value), value) {
+                (Some(adds), _) => dict.extend(adds.clone()),
+                (None, Value::Table(items)) => dict = items.clone(),
+                _ => return Ok(last.clone()),
+            }
+        }
+        return Ok(Value::Table(dict));
+    }
+    Ok(last.clone())
+}
+
+// Converts a TOML value to JSON for `Config::to_canonical_json`, inserting the keys of each table
+// in sorted order so that the JSON is sorted regardless of how serde_json orders its maps.
+fn toml_value_to_canonical_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::String(s) => serde_json::Value::String(s.to_owned()),
+        Value::Integer(i) => serde_json::Value::from(*i),
+        Value::Float(f) if f.is_nan() => serde_json::Value::from("nan"),
+        Value::Float(f) if f.is_infinite() => {
+            serde_json::Value::from(if *f > 0.0 { "inf" } else { "-inf" })
+        }
+        Value::Float(f) => serde_json::Value::from(*f),
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+        Value::Datetime(d) => serde_json::Value::String(d.to_string()),
+        Value::Array(a) => {
+            serde_json::Value::Array(a.iter().map(toml_value_to_canonical_json).collect())
+        }
+        Value::Table(t) => {
+            let mut entries = t.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), toml_value_to_canonical_json(v)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+// Converts a JSON value to the equivalent TOML value, or returns None if it is or contains a
+// null, or a number that TOML can't represent, for which there is no equivalent.
+fn json_to_toml_value(value: &serde_json::Value) -> Option<Value> {
+    Some(match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None if n.is_u64() => return None,
+            None => Value::Float(n.as_f64()?),
+        },
+        serde_json::Value::String(s) => Value::String(s.to_owned()),
+        serde_json::Value::Array(a) => Value::Array(
+            a.iter()
+                .map(json_to_toml_value)
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        serde_json::Value::Object(o) => Value::Table(
+            o.iter()
+                .map(|(k, v)| json_to_toml_value(v).map(|v| (k.to_owned(), v)))
+                .collect::<Option<Table>>()?,
+        ),
+    })
+}
+
 fn toml_value_to_val(value: &Value) -> Val {
     match value {
         Value::String(s) => Val::String(s.to_owned()),
@@ -229,20 +1055,74 @@ fn toml_table_to_dict(table: &Value) -> HashMap<String, Val> {
     }
 }
 
+// The candidate locations of the user's config, in priority order, given the value of each
+// environment variable.
+pub(crate) fn user_config_paths(env_var: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    let dir_var = |name: &str| {
+        env_var(name)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    };
+    let mut config_dirs = vec![];
+    config_dirs.extend(dir_var("XDG_CONFIG_HOME"));
+    if cfg!(windows) {
+        config_dirs.extend(dir_var("APPDATA"));
+    } else if let Some(home) = dir_var("HOME") {
+        if cfg!(target_os = "macos") {
+            config_dirs.push(home.join("Library").join("Application Support"));
+        }
+        config_dirs.push(home.join(".config"));
+    }
+    config_dirs
+        .into_iter()
+        .map(|dir| dir.join("pants").join("config.toml"))
+        .collect()
+}
+
 #[derive(Clone, Debug)]
 pub struct ConfigSource {
     pub path: PathBuf,
     pub content: String,
 }
 
+// Config files are expected to be small, so this is a generous guard against accidentally
+// reading something enormous.
+const DEFAULT_MAX_CONFIG_BYTES: u64 = 16 * 1024 * 1024;
+
 impl ConfigSource {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ConfigSource, String> {
-        let content = fs::read_to_string(&path).map_err(|e| {
+        Self::from_file_with_max_bytes(path, DEFAULT_MAX_CONFIG_BYTES)
+    }
+
+    ///
+    /// Reads the config file at `path`, erroring without reading its content if it is larger than
+    /// `max_bytes`.
+    ///
+    pub fn from_file_with_max_bytes<P: AsRef<Path>>(
+        path: P,
+        max_bytes: u64,
+    ) -> Result<ConfigSource, String> {
+        let read_error = |e: std::io::Error| {
             format!(
                 "Failed to read config file {}: {}",
                 path.as_ref().display(),
                 e
             )
+        };
+        let size = fs::metadata(&path).map_err(read_error)?.len();
+        if size > max_bytes {
+            return Err(format!(
+                "Config file {} exceeds maximum size of {max_bytes} bytes",
+                path.as_ref().display()
+            ));
+        }
+        let bytes = fs::read(&path).map_err(read_error)?;
+        let content = String::from_utf8(bytes).map_err(|e| {
+            format!(
+                "Config file {} is not valid UTF-8 (first bad byte at offset {})",
+                path.as_ref().display(),
+                e.utf8_error().valid_up_to()
+            )
         })?;
         Ok(ConfigSource {
             path: path.as_ref().to_path_buf(),
@@ -251,108 +1131,2237 @@ impl ConfigSource {
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct Config {
-    value: Value,
+/// Controls how section names are cased when a Config is serialized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SectionCase {
+    /// Section names are written exactly as they appear in the config.
+    Preserve,
+    /// Section names are lowercased.
+    Lowercase,
+    /// Section names are lowercased, except for GLOBAL and DEFAULT, which are uppercased.
+    Canonical,
 }
 
-impl Config {
-    pub(crate) fn parse(
-        config_source: &ConfigSource,
-        seed_values: &InterpolationMap,
-    ) -> Result<Config, String> {
-        let config = config_source.content.parse::<Value>().map_err(|e| {
-            format!(
-                "Failed to parse config file {}: {}",
-                config_source.path.display(),
-                e
-            )
-        })?;
-
-        fn add_section_to_interpolation_map(
-            mut imap: InterpolationMap,
-            section: Option<&Value>,
-        ) -> Result<InterpolationMap, String> {
-            if let Some(section) = section {
-                if let Some(table) = section.as_table() {
-                    for (key, value) in table.iter() {
-                        if let Value::String(s) = value {
-                            imap.insert(key.clone(), s.clone());
-                        }
-                    }
+impl SectionCase {
+    fn apply(&self, section_name: &str) -> String {
+        match self {
+            SectionCase::Preserve => section_name.to_owned(),
+            SectionCase::Lowercase => section_name.to_lowercase(),
+            SectionCase::Canonical => {
+                let upper = section_name.to_uppercase();
+                if upper == "GLOBAL" || upper == DEFAULT_SECTION {
+                    upper
+                } else {
+                    section_name.to_lowercase()
                 }
             }
-            Ok(imap)
         }
+    }
+}
 
-        let default_imap =
-            add_section_to_interpolation_map(seed_values.clone(), config.get(DEFAULT_SECTION))?;
-
-        let new_sections: Result<Vec<(String, Value)>, String> = match config {
-            Value::Table(t) => t
-                .into_iter()
-                .map(|(section_name, section)| {
-                    if !section.is_table() {
-                        return Err(format!(
-                            "Expected the config file {} to contain tables per section, \
-                            but section {} contained a {}: {}",
-                            config_source.path.display(),
-                            section_name,
-                            section.type_str(),
-                            section
-                        ));
-                    }
-                    let section_imap = if section_name == *DEFAULT_SECTION {
-                        default_imap.clone()
-                    } else {
-                        add_section_to_interpolation_map(default_imap.clone(), Some(&section))?
-                    };
-                    let new_section = interpolate_value("", section.clone(), &section_imap)
-                        .map_err(|e| {
-                            format!(
-                                "{} in config file {}, section {}, key {}",
-                                e.msg,
-                                config_source.path.display(),
-                                section_name,
-                                e.key
-                            )
-                        })?;
-                    Ok((section_name, new_section))
-                })
-                .collect(),
+/// A difference in a single option between two Configs, as computed by `Config::diff`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigChange {
+    Added {
+        section: String,
+        option: String,
+        value: Value,
+    },
+    Removed {
+        section: String,
+        option: String,
+        value: Value,
+    },
+    Changed {
+        section: String,
+        option: String,
+        old: Value,
+        new: Value,
+    },
+}
 
-            _ => Err(format!(
-                "Expected the config file {} to contain a table but contained a {}: {}",
-                config_source.path.display(),
-                config.type_str(),
-                config
-            )),
-        };
+///
+/// Structured notes attached to an option by `# @pants: <key> <value>` comments on the lines
+/// directly above it, e.g.:
+///
+/// ```toml
+/// [python]
+/// # @pants: deprecated since 2.20
+/// # @pants: owner build-infra
+/// # @pants: ticket INFRA-123
+/// resolves_generate_lockfiles = false
+/// ```
+///
+/// Other comments may be interleaved with annotations, but a blank line or a section header
+/// discards any annotations not yet attached to an option. Unrecognized keys are ignored.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OptionMetadata {
+    pub deprecated: Option<String>,
+    pub owner: Option<String>,
+    pub ticket: Option<String>,
+}
 
-        let new_table = Table::from_iter(new_sections?);
-        Ok(Self {
-            value: Value::Table(new_table),
-        })
+impl OptionMetadata {
+    fn is_empty(&self) -> bool {
+        self == &OptionMetadata::default()
     }
 }
 
-pub(crate) struct ConfigReader {
-    config: Config,
-    fromfile_expander: FromfileExpander,
-}
+fn parse_option_metadata(content: &str) -> HashMap<(String, String), OptionMetadata> {
+    let mut metadata = HashMap::new();
+    let mut section_name = "";
+    let mut pending = OptionMetadata::default();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() {
+            pending = OptionMetadata::default();
+        } else if let Some(annotation) = ANNOTATION_RE.captures(line) {
+            let value = annotation[2].to_owned();
+            match &annotation[1] {
+                "deprecated" => pending.deprecated = Some(value),
+                "owner" => pending.owner = Some(value),
+                "ticket" => pending.ticket = Some(value),
+                _ => (),
+            }
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(header) = SECTION_HEADER_RE.captures(line) {
+            section_name = header.get(1).unwrap().as_str();
+            pending = OptionMetadata::default();
+        } else if let Some(key) = OPTION_KEY_RE.captures(line) {
+            if !pending.is_empty() {
+                metadata.insert(
+                    (section_name.to_owned(), key[1].to_owned()),
+                    std::mem::take(&mut pending),
+                );
+            }
+        }
+    }
+    metadata
+}
+
+// Finds the sections annotated with a `# @pants: example` comment, which are examples for
+// documentation only, rather than active config.
+fn parse_example_sections(content: &str) -> HashSet<String> {
+    let mut example_sections = HashSet::new();
+    let mut pending = false;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() {
+            pending = false;
+        } else if let Some(annotation) = ANNOTATION_RE.captures(line) {
+            pending |= &annotation[1] == "example";
+        } else if line.starts_with('#') {
+            continue;
+        } else {
+            if let Some(header) = SECTION_HEADER_RE.captures(line).filter(|_| pending) {
+                example_sections.insert(header[1].trim_matches('\'').to_owned());
+            }
+            pending = false;
+        }
+    }
+    example_sections
+}
+
+/// The severity of a warning reported while constructing a Config.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    // Likely cruft that is harmless, but worth cleaning up.
+    Info,
+    // Config that is deprecated, or that may not have the effect that was intended.
+    Warning,
+}
+
+/// A relationship that the values of two numeric options must have, for use with
+/// `ConfigReader::check_relation`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Relation {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+impl Relation {
+    fn holds(&self, a: i64, b: i64) -> bool {
+        match self {
+            Relation::Lt => a < b,
+            Relation::Le => a <= b,
+            Relation::Eq => a == b,
+            Relation::Ne => a != b,
+            Relation::Ge => a >= b,
+            Relation::Gt => a > b,
+        }
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = match self {
+            Relation::Lt => "<",
+            Relation::Le => "<=",
+            Relation::Eq => "==",
+            Relation::Ne => "!=",
+            Relation::Ge => ">=",
+            Relation::Gt => ">",
+        };
+        write!(f, "{operator}")
+    }
+}
+
+// The line of a config file on which an option is set.
+#[derive(Clone, Debug)]
+struct OptionLocation {
+    // The 1-based line number.
+    line: usize,
+    // The length of the line, in characters.
+    len: usize,
+    // If the option is set to an array that spans multiple lines, the first line of an item that
+    // is indented differently from the item before it.
+    misindented_line: Option<usize>,
+}
+
+// The net number of array brackets that `line` opens, ignoring those in strings and comments.
+fn bracket_depth_change(line: &str) -> isize {
+    let mut change = 0;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => break,
+            (None, '[') => change += 1,
+            (None, ']') => change -= 1,
+            _ => (),
+        }
+    }
+    change
+}
+
+// Finds the line on which each option is first set in the given config content. This is a line
+// scan rather than a full parse, in the same way as `parse_option_metadata`.
+fn parse_option_locations(content: &str) -> HashMap<(String, String), OptionLocation> {
+    let mut locations: HashMap<(String, String), OptionLocation> = HashMap::new();
+    let mut section_name = "";
+    // While in a multi-line array: its option, its depth of brackets, and the indentation of
+    // its first item.
+    let mut array: Option<((String, String), isize, Option<&str>)> = None;
+    for (i, raw_line) in content.lines().enumerate() {
+        let line = raw_line.trim();
+        if let Some((key, depth, indent)) = &mut array {
+            if !line.is_empty() && !line.starts_with('#') {
+                let line_indent = &raw_line[..raw_line.len() - raw_line.trim_start().len()];
+                if *depth == 1 && !line.starts_with(']') {
+                    match indent {
+                        Some(indent) if *indent != line_indent => {
+                            if let Some(location) = locations.get_mut(key) {
+                                location.misindented_line.get_or_insert(i + 1);
+                            }
+                        }
+                        Some(_) => (),
+                        None => *indent = Some(line_indent),
+                    }
+                }
+                *depth += bracket_depth_change(line);
+            }
+            if *depth <= 0 {
+                array = None;
+            }
+        } else if let Some(header) = SECTION_HEADER_RE.captures(line) {
+            section_name = header.get(1).unwrap().as_str();
+        } else if let Some(key) = OPTION_KEY_RE.captures(line) {
+            let key = (section_name.to_owned(), key[1].to_owned());
+            let depth = bracket_depth_change(line);
+            if depth > 0 && !locations.contains_key(&key) {
+                array = Some((key.clone(), depth, None));
+            }
+            locations.entry(key).or_insert_with(|| OptionLocation {
+                line: i + 1,
+                len: raw_line.chars().count(),
+                misindented_line: None,
+            });
+        }
+    }
+    locations
+}
+
+// The depth to which inline tables are nested in the given value, where a table that contains no
+// tables has depth 1.
+fn inline_table_depth(value: &Value) -> usize {
+    match value {
+        Value::Table(table) => 1 + table.values().map(inline_table_depth).max().unwrap_or(0),
+        Value::Array(items) => items.iter().map(inline_table_depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+// The items that appear more than once in `items`, in order of their second appearance.
+fn duplicate_items(items: &[Value]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    items
+        .iter()
+        .map(Value::to_string)
+        .filter(|item| !seen.insert(item.clone()))
+        .collect()
+}
+
+// Renders `value` as it would be written on a single line of TOML, with all tables inline.
+fn inline_toml(value: &Value) -> String {
+    match value {
+        Value::Array(items) => format!("[{}]", items.iter().map(inline_toml).join(", ")),
+        Value::Table(table) if table.is_empty() => "{}".to_owned(),
+        Value::Table(table) => format!(
+            "{{ {} }}",
+            table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", toml_key(key), inline_toml(value)))
+                .join(", ")
+        ),
+        scalar => scalar.to_string(),
+    }
+}
+
+// Renders `key` as a TOML key, quoting it unless it is a bare key.
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_owned()
+    } else {
+        Value::String(key.to_owned()).to_string()
+    }
+}
+
+/// A stylistic problem with a config, as found by `Config::lint`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LintWarning {
+    /// The name of the source that set the option.
+    pub source: String,
+    /// The 1-based line of the source that the warning is about, if known: usually the line on
+    /// which the option is set.
+    pub line: Option<usize>,
+    pub section: String,
+    pub option: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        write!(f, ": [{}] {}: {}", self.section, self.option, self.message)
+    }
+}
+
+/// Why a Config has no value for an option, as explained by `Config::explain_unset`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnsetReason {
+    /// There is no section for the option's scope (nor for any deprecated alias of it), and
+    /// DEFAULT doesn't set it.
+    ScopeMissing,
+    /// The scope's section exists, but neither it nor DEFAULT sets the option.
+    OptionMissing,
+    /// The option is set to just `{clear = true}`, which leaves it empty.
+    ExplicitlyCleared,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    value: Value,
+    // The name of the source that set each option, keyed by section and option name.
+    origins: HashMap<(String, String), String>,
+    // The environment variables that `from_env` values may read, taken from the `env.` seeds.
+    env: HashMap<String, String>,
+    // Maps each deprecated scope name to the name of the scope that replaced it.
+    scope_aliases: HashMap<String, String>,
+    // Maps the name of each section that declares `_inherits` to the scope that it inherits from.
+    inherits: HashMap<String, String>,
+    // The annotations attached to each option, keyed by section and option name.
+    metadata: HashMap<(String, String), OptionMetadata>,
+    // The options that configs merged on top of this one may not override, keyed by section and
+    // option name.
+    locked: HashSet<(String, String)>,
+    // The line on which each option is set in its source, keyed by section and option name.
+    locations: HashMap<(String, String), OptionLocation>,
+    warnings: Vec<String>,
+    warning_sink: Option<WarningSink>,
+}
+
+impl Config {
+    ///
+    /// Parses the given config, interpolating `%(name)s` placeholders with `seed_values` and the
+    /// values of the DEFAULT section and of the placeholder's own section.
+    ///
+    /// A string may also reference an option in another TOML config file, as in
+    /// `${path.toml:scope.option}`, where a relative path is relative to the directory of this
+    /// config. A string consisting of just such a reference takes on the referenced value,
+    /// whatever its type.
+    ///
+    /// A section may declare `_inherits = "scope"`, in which case the options that it doesn't set
+    /// are read from the section of that scope (and so on, if that scope inherits in turn). List
+    /// and dict edits in the section apply on top of those of the scope it inherits from.
+    ///
+    /// A section preceded by a `# @pants: example` comment is an example for documentation only,
+    /// and is skipped entirely.
+    ///
+    pub fn parse(
+        config_source: &ConfigSource,
+        seed_values: &InterpolationMap,
+    ) -> Result<Config, String> {
+        Self::parse_with_file_refs(config_source, seed_values, &mut FileRefs::default())
+    }
+
+    ///
+    /// Parses the given config as `parse` does, except that `%(env.X)s` placeholders may only
+    /// interpolate the env vars named in `env_allowlist`: any other is an error, even if the env
+    /// var is set. This prevents a config from exfiltrating arbitrary values from the environment.
+    ///
+    pub fn parse_with_env_allowlist(
+        config_source: &ConfigSource,
+        seed_values: &InterpolationMap,
+        env_allowlist: &HashSet<String>,
+    ) -> Result<Config, String> {
+        let mut file_refs = FileRefs {
+            env_allowlist: Some(env_allowlist.clone()),
+            ..FileRefs::default()
+        };
+        Self::parse_with_file_refs(config_source, seed_values, &mut file_refs)
+    }
+
+    fn parse_with_file_refs(
+        config_source: &ConfigSource,
+        seed_values: &InterpolationMap,
+        file_refs: &mut FileRefs,
+    ) -> Result<Config, String> {
+        let mut config = config_source.content.parse::<Value>().map_err(|e| {
+            format!(
+                "Failed to parse config file {}: {}",
+                config_source.path.display(),
+                e
+            )
+        })?;
+        let config_version = match config
+            .as_table_mut()
+            .and_then(|table| table.remove(CONFIG_VERSION_KEY))
+        {
+            Some(Value::Integer(version)) if version > CONFIG_VERSION => {
+                return Err(format!(
+                    "Config version {version} is newer than supported version {CONFIG_VERSION}; \
+                    upgrade Pants"
+                ));
+            }
+            Some(Value::Integer(version)) => Some(version),
+            Some(value) => {
+                return Err(format!(
+                    "Expected {CONFIG_VERSION_KEY} in config file {} to be an int but given {value}",
+                    config_source.path.display()
+                ));
+            }
+            None => None,
+        };
+        // Example sections are dropped before interpolation, since they needn't be valid for this
+        // repo: e.g. they may use placeholders that aren't defined.
+        if let Some(table) = config.as_table_mut() {
+            for section_name in parse_example_sections(&config_source.content) {
+                table.remove(&section_name);
+            }
+        }
+
+        fn add_section_to_interpolation_map(
+            mut imap: InterpolationMap,
+            section: Option<&Value>,
+        ) -> Result<InterpolationMap, String> {
+            if let Some(section) = section {
+                if let Some(table) = section.as_table() {
+                    for (key, value) in table.iter() {
+                        if let Value::String(s) = value {
+                            imap.insert(key.clone(), s.clone());
+                        }
+                    }
+                }
+            }
+            Ok(imap)
+        }
+
+        let mut seed_imap = builtin_placeholders();
+        seed_imap.extend(seed_values.clone());
+        let default_imap =
+            add_section_to_interpolation_map(seed_imap, config.get(DEFAULT_SECTION))?;
+
+        // All values are interpolated exactly once, here: the Config holds only the resolved values,
+        // so reads never re-run substitution, and there is no interpolation cache to invalidate.
+        // A Config parsed with different seed values is a distinct Config.
+        let new_sections: Result<Vec<(String, Value)>, String> = match config {
+            Value::Table(t) => t
+                .into_iter()
+                .map(|(section_name, section)| {
+                    if !section.is_table() {
+                        return Err(format!(
+                            "Expected the config file {} to contain tables per section, \
+                            but section {} contained a {}: {}",
+                            config_source.path.display(),
+                            section_name,
+                            section.type_str(),
+                            section
+                        ));
+                    }
+                    let section_imap = if section_name == *DEFAULT_SECTION {
+                        default_imap.clone()
+                    } else {
+                        add_section_to_interpolation_map(default_imap.clone(), Some(&section))?
+                    };
+                    let new_section = interpolate_value(
+                        "",
+                        section.clone(),
+                        &section_imap,
+                        file_refs.env_allowlist.as_ref(),
+                    )
+                    .map_err(|e| {
+                        format!(
+                            "{} in config file {}, section {}, key {}",
+                            e.msg,
+                            config_source.path.display(),
+                            section_name,
+                            e.key
+                        )
+                    })?;
+                    Ok((section_name, new_section))
+                })
+                .collect(),
+
+            _ => Err(format!(
+                "Expected the config file {} to contain a table but contained a {}: {}",
+                config_source.path.display(),
+                config.type_str(),
+                config
+            )),
+        };
+
+        // References to other files are resolved after interpolation, since the values that they
+        // refer to have already been interpolated in the context of their own files.
+        file_refs.parsing.push(
+            fs::canonicalize(&config_source.path).unwrap_or_else(|_| config_source.path.clone()),
+        );
+        let config_dir = config_source.path.parent().unwrap_or(Path::new(""));
+        let resolved = new_sections?
+            .into_iter()
+            .map(|(section_name, section)| {
+                let new_section = file_refs
+                    .resolve_value("", section, config_dir, seed_values)
+                    .map_err(|e| {
+                        format!(
+                            "{} in config file {}, section {}, key {}",
+                            e.msg,
+                            config_source.path.display(),
+                            section_name,
+                            e.key
+                        )
+                    })?;
+                Ok((section_name, new_section))
+            })
+            .collect::<Result<Table, String>>();
+        file_refs.parsing.pop();
+
+        let mut sections = resolved?;
+        let locked = match sections.remove(LOCKED_SECTION) {
+            Some(section) => parse_locked_options(&section)
+                .map_err(|e| format!("{e} in config file {}", config_source.path.display()))?,
+            None => HashSet::new(),
+        };
+        let inherits = take_inherits(&mut sections)
+            .map_err(|e| format!("{e} in config file {}", config_source.path.display()))?;
+        if let Some(cycle) = inheritance_cycle(&inherits) {
+            return Err(format!(
+                "Scopes inherit from each other in a cycle: {} in config file {}",
+                cycle.join(" -> "),
+                config_source.path.display()
+            ));
+        }
+        let mut config = Config::from_table(sections, &config_source.path.display().to_string());
+        config.locked = locked;
+        config.inherits = inherits;
+        config.env = seed_values
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix("env.")
+                    .map(|name| (name.to_owned(), value.clone()))
+            })
+            .collect();
+        config.metadata = parse_option_metadata(&config_source.content);
+        config.locations = parse_option_locations(&config_source.content);
+        if let Some(version) = config_version.filter(|version| *version < CONFIG_VERSION) {
+            config.warn(
+                Severity::Warning,
+                format!(
+                    "Config file {} is for config version {version}, which is older than the \
+                    supported version {CONFIG_VERSION}: some options may have changed meaning",
+                    config_source.path.display()
+                ),
+            );
+        }
+        Ok(config)
+    }
+
+    ///
+    /// Creates a Config from an already parsed and interpolated table of sections, attributing
+    /// all of its options to `source_name`.
+    ///
+    pub(crate) fn from_table(table: Table, source_name: &str) -> Config {
+        let mut origins = HashMap::new();
+        for (section_name, section) in table.iter() {
+            if let Some(section) = section.as_table() {
+                for option_name in section.keys() {
+                    origins.insert(
+                        (section_name.clone(), option_name.clone()),
+                        source_name.to_owned(),
+                    );
+                }
+            }
+        }
+        Config {
+            value: Value::Table(table),
+            origins,
+            env: HashMap::new(),
+            scope_aliases: HashMap::new(),
+            inherits: HashMap::new(),
+            metadata: HashMap::new(),
+            locked: HashSet::new(),
+            locations: HashMap::new(),
+            warnings: vec![],
+            warning_sink: None,
+        }
+    }
+
+    ///
+    /// Parses config read from an arbitrary stream, such as a decompressed archive member or a
+    /// network response, using `source_name` in place of a file path in error messages.
+    ///
+    pub fn parse_reader<R: Read>(
+        mut reader: R,
+        source_name: &str,
+        seed_values: &InterpolationMap,
+    ) -> Result<Config, String> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .map_err(|e| format!("Failed to read config file {source_name}: {e}"))?;
+        Config::parse(
+            &ConfigSource {
+                path: PathBuf::from(source_name),
+                content,
+            },
+            seed_values,
+        )
+    }
+
+    ///
+    /// Loads the user's machine-wide config, if any, from the first of these that exists:
+    /// - `$XDG_CONFIG_HOME/pants/config.toml`
+    /// - `%APPDATA%\pants\config.toml` (on Windows)
+    /// - `~/Library/Application Support/pants/config.toml` (on macOS)
+    /// - `~/.config/pants/config.toml` (on Unix, including macOS)
+    ///
+    /// The user config is intended to provide defaults beneath the repo's own config, by merging
+    /// the repo config on top of it. Since it is not specific to any repo, it is parsed without
+    /// seed values, and so may only interpolate values it defines itself.
+    ///
+    pub fn load_user_config() -> Result<Option<Config>, String> {
+        Self::load_user_config_from(|name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn load_user_config_from(
+        env_var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Config>, String> {
+        for path in user_config_paths(env_var) {
+            if path.is_file() {
+                let config_source = ConfigSource::from_file(&path)?;
+                return Config::parse(&config_source, &InterpolationMap::new()).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    ///
+    /// Returns the warnings collected while constructing this Config.
+    ///
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    ///
+    /// Sets a callback to report each warning to as it is recorded, such as to emit it via our
+    /// logging. Warnings are still collected, and warnings recorded before the sink was set are
+    /// not reported to it. Configs derived from this one, e.g. by `merge`, share the sink.
+    ///
+    pub fn set_warning_sink(&mut self, sink: WarningSink) {
+        self.warning_sink = Some(sink);
+    }
+
+    fn warn(&mut self, severity: Severity, message: String) {
+        if let Some(warning_sink) = &self.warning_sink {
+            warning_sink(severity, &message);
+        }
+        self.warnings.push(message);
+    }
+
+    ///
+    /// Returns this Config with the given scope renames applied, where `scope_aliases` maps each
+    /// deprecated scope name to the name of the scope that replaced it.
+    ///
+    /// Reads for either scope of a pair consult both sections, with values in the section of the
+    /// new scope taking precedence. A deprecation warning is recorded for each old scope section
+    /// present in this Config.
+    ///
+    pub fn with_scope_aliases(mut self, scope_aliases: HashMap<String, String>) -> Config {
+        let mut sorted_aliases = scope_aliases.iter().collect::<Vec<_>>();
+        sorted_aliases.sort();
+        for (old_scope, new_scope) in sorted_aliases {
+            if self.value.get(old_scope).is_some() {
+                self.warn(
+                    Severity::Warning,
+                    format!(
+                        "Config section [{old_scope}] is deprecated: use [{new_scope}] instead"
+                    ),
+                );
+            }
+        }
+        self.scope_aliases.extend(scope_aliases);
+        self
+    }
+
+    ///
+    /// Returns this Config with a warning recorded for each section that contains no options.
+    ///
+    /// An empty section is valid TOML, but is usually left over from removed options or is a typo.
+    ///
+    pub fn with_empty_section_warnings(mut self) -> Config {
+        let empty_sections = self
+            .sections()
+            .into_iter()
+            .filter(|section_name| self.option_names(section_name).is_empty())
+            .map(|section_name| format!("Config section [{section_name}] contains no options"))
+            .collect::<Vec<_>>();
+        for message in empty_sections {
+            self.warn(Severity::Info, message);
+        }
+        self
+    }
+
+    ///
+    /// Errors if the name of any section in this Config collides with or looks like a misspelling
+    /// of a reserved section name, which would otherwise be silently treated as the section of an
+    /// unrelated scope.
+    ///
+    /// A section whose name differs from a reserved name only by case, such as `[default]`, is a
+    /// collision: it is a valid scope name, but likely intended as the reserved section, so the
+    /// scope must be renamed to disambiguate. Otherwise, a section within a single edit of a
+    /// reserved name, such as `[DEFALT]`, is a misspelling, and the error suggests the correction.
+    ///
+    pub fn validate_reserved_sections(&self) -> Result<(), String> {
+        for section_name in self.sections() {
+            if RESERVED_SECTIONS.contains(&section_name) {
+                continue;
+            }
+            let lowercase_name = section_name.to_ascii_lowercase();
+            for reserved_name in RESERVED_SECTIONS {
+                let distance = edit_distance(&lowercase_name, &reserved_name.to_ascii_lowercase());
+                if distance == 0 {
+                    return Err(format!(
+                        "Config section [{section_name}] collides with the reserved section \
+                        [{reserved_name}]: rename the {section_name} scope, or use \
+                        [{reserved_name}] if the reserved section was intended"
+                    ));
+                } else if distance == 1 {
+                    return Err(format!(
+                        "Config section [{section_name}] looks like a misspelling of the reserved \
+                        section [{reserved_name}]: did you mean [{reserved_name}]?"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Checks that every section and option name is ASCII, erroring with a message for each name
+    /// that isn't. This catches homoglyph typos, such as a Cyrillic `а` in place of a Latin `a`,
+    /// which would otherwise silently fail to match the intended scope or option.
+    ///
+    pub fn validate_ascii_names(&self) -> Result<(), Vec<String>> {
+        fn non_ascii_char(name: &str) -> Option<char> {
+            name.chars().find(|c| !c.is_ascii())
+        }
+
+        let mut errors = vec![];
+        for section_name in self.sections() {
+            if let Some(c) = non_ascii_char(section_name) {
+                errors.push(format!(
+                    "Section name [{section_name}] contains the non-ASCII character '{c}' \
+                    (U+{:04X})",
+                    c as u32
+                ));
+            }
+            for option_name in self.option_names(section_name) {
+                if let Some(c) = non_ascii_char(option_name) {
+                    errors.push(format!(
+                        "Option name {option_name} in [{section_name}] contains the non-ASCII \
+                        character '{c}' (U+{:04X})",
+                        c as u32
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    ///
+    /// Checks that no option still has a placeholder value, as shipped in config templates, such
+    /// as `"CHANGEME"` or `"<set me>"`. A string value, or a string in an array or table value, is
+    /// a placeholder if any of `placeholders` matches it.
+    ///
+    /// Note that each placeholder pattern should be anchored (e.g. `^<.*>$`) to match whole values.
+    ///
+    pub fn validate_placeholders(&self, placeholders: &[Regex]) -> Result<(), String> {
+        fn find_placeholder<'a>(value: &'a Value, placeholders: &[Regex]) -> Option<&'a str> {
+            match value {
+                Value::String(s) if placeholders.iter().any(|re| re.is_match(s)) => Some(s),
+                Value::Array(items) => items
+                    .iter()
+                    .find_map(|item| find_placeholder(item, placeholders)),
+                Value::Table(table) => table
+                    .values()
+                    .find_map(|item| find_placeholder(item, placeholders)),
+                _ => None,
+            }
+        }
+
+        for section_name in self.sections() {
+            for option_name in self.option_names(section_name) {
+                let value = self.get(section_name, option_name).unwrap();
+                if let Some(placeholder) = find_placeholder(value, placeholders) {
+                    return Err(format!(
+                        "Option [{section_name}] {option_name} still has placeholder value \
+                        '{placeholder}'"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Returns a copy of this Config that is safe to log or to include in a bug report, in which
+    /// the values of the options named in `secret_keys` (as `scope.option`), the values read
+    /// `from_env`, and any `@secret:` strings within values are replaced with `"***"`. All other
+    /// values are preserved verbatim.
+    ///
+    pub fn sanitized(&self, secret_keys: &HashSet<String>) -> Config {
+        const MASK: &str = "***";
+
+        fn mask_secrets(value: &mut Value) {
+            match value {
+                Value::String(s) if s.starts_with("@secret:") => {
+                    *value = Value::String(MASK.into())
+                }
+                Value::Array(items) => items.iter_mut().for_each(mask_secrets),
+                Value::Table(_) if from_env_var_name(value).is_some() => {
+                    *value = Value::String(MASK.into())
+                }
+                Value::Table(table) => table.iter_mut().for_each(|(_, item)| mask_secrets(item)),
+                _ => (),
+            }
+        }
+
+        let mut sanitized = self.clone();
+        if let Some(sections) = sanitized.value.as_table_mut() {
+            for (section_name, section) in sections.iter_mut() {
+                if let Some(options) = section.as_table_mut() {
+                    for (option_name, value) in options.iter_mut() {
+                        if secret_keys.contains(&format!("{section_name}.{option_name}")) {
+                            *value = Value::String(MASK.into());
+                        } else {
+                            mask_secrets(value);
+                        }
+                    }
+                }
+            }
+        }
+        sanitized
+    }
+
+    ///
+    /// Checks this Config for options that are set in a way that is valid, but hard to read or
+    /// likely to be a mistake:
+    ///
+    /// - an inline array on a line longer than 100 characters,
+    /// - inline tables nested more than 2 deep,
+    /// - an item listed more than once in a list, or in the `add` or `remove` of a list edit,
+    /// - an item that a list edit both adds and removes,
+    /// - an item of a multi-line array that is indented differently from the item before it,
+    ///   which is often a sign of a templating bug.
+    ///
+    /// Note that a missing comma between the items of a multi-line array is not a lint: TOML
+    /// doesn't join such items, but fails to parse them.
+    ///
+    /// The line of each warning is found by scanning the text of the option's source, so it is
+    /// unknown for options that weren't parsed from a config file. Warnings are ordered by line,
+    /// and then by section and option, so that they follow the file.
+    ///
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let mut lints = vec![];
+        for section_name in self.sections() {
+            for option_name in self.option_names(section_name) {
+                let value = self.get(section_name, option_name).unwrap();
+                let key = (section_name.to_owned(), option_name.to_owned());
+                let location = self.locations.get(&key);
+                let mut lint_line = |line: Option<usize>, message: String| {
+                    lints.push(LintWarning {
+                        source: self.origin(section_name, option_name).to_owned(),
+                        line,
+                        section: section_name.to_owned(),
+                        option: option_name.to_owned(),
+                        message,
+                    })
+                };
+
+                if let Some(line) = location.and_then(|location| location.misindented_line) {
+                    lint_line(
+                        Some(line),
+                        "Item of multi-line array is indented differently from the item before \
+                        it"
+                        .to_owned(),
+                    );
+                }
+                let mut lint = |message| lint_line(location.map(|location| location.line), message);
+                if let (Value::Array(_), Some(location)) = (value, location) {
+                    if location.len > MAX_INLINE_ARRAY_LINE_LEN {
+                        lint(format!(
+                            "Inline array is on a line of {} characters: consider writing one \
+                            item per line",
+                            location.len
+                        ));
+                    }
+                }
+                let depth = inline_table_depth(value);
+                if depth > MAX_INLINE_TABLE_DEPTH {
+                    lint(format!(
+                        "Inline tables are nested {depth} deep: consider using a separate section"
+                    ));
+                }
+                match value {
+                    Value::Array(items) => {
+                        for item in duplicate_items(items) {
+                            lint(format!("{item} is listed more than once"));
+                        }
+                    }
+                    Value::Table(edits) if is_list_edit_table(edits) => {
+                        let items = |key| {
+                            edits
+                                .get(key)
+                                .and_then(Value::as_array)
+                                .cloned()
+                                .unwrap_or_default()
+                        };
+                        let adds = items("add");
+                        let removes = items("remove");
+                        for item in duplicate_items(&adds) {
+                            lint(format!("{item} is added more than once"));
+                        }
+                        for item in duplicate_items(&removes) {
+                            lint(format!("{item} is removed more than once"));
+                        }
+                        let added: HashSet<String> = adds.iter().map(Value::to_string).collect();
+                        for item in removes.iter().map(Value::to_string) {
+                            if added.contains(&item) {
+                                lint(format!("{item} is both added and removed"));
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        // A stable sort, so that the warnings of an option keep the order they were found in.
+        lints.sort_by(|a, b| (a.line, &a.section, &a.option).cmp(&(b.line, &b.section, &b.option)));
+        lints
+    }
+
+    ///
+    /// Explains why reading the option `id` from this Config finds no value, or returns None if
+    /// it does find one. This inspects the sections that the option would be read from, without
+    /// resolving its value.
+    ///
+    pub fn explain_unset(&self, id: &OptionId) -> Option<UnsetReason> {
+        let scope_sections = self.scope_sections(id.scope.name());
+        match self.get_value(id) {
+            Some(Value::Table(edits))
+                if edits.len() == 1 && edits.get("clear") == Some(&Value::Boolean(true)) =>
+            {
+                Some(UnsetReason::ExplicitlyCleared)
+            }
+            Some(_) => None,
+            None if scope_sections
+                .iter()
+                .any(|section_name| self.value.get(section_name).is_some()) =>
+            {
+                Some(UnsetReason::OptionMissing)
+            }
+            None => Some(UnsetReason::ScopeMissing),
+        }
+    }
+
+    // Returns the sections that may hold options for the given scope, in priority order: the
+    // section of the scope itself (after resolving any alias), followed by the sections of any
+    // deprecated scopes that alias it, and then in the same way for the scope it `_inherits` from
+    // (if any), and so on up the chain of inheritance.
+    fn scope_sections<'a>(&'a self, scope_name: &'a str) -> Vec<&'a str> {
+        let mut sections = vec![];
+        let mut next_scope = Some(scope_name);
+        while let Some(scope_name) = next_scope {
+            let scope_name = self
+                .scope_aliases
+                .get(scope_name)
+                .map(String::as_str)
+                .unwrap_or(scope_name);
+            // A cycle is warned about when it is created, and reading stops at the first repeat.
+            if sections.contains(&scope_name) {
+                break;
+            }
+            let mut aliases = self
+                .scope_aliases
+                .iter()
+                .filter(|(_, new_scope)| *new_scope == scope_name)
+                .map(|(old_scope, _)| old_scope.as_str())
+                .collect::<Vec<_>>();
+            aliases.sort();
+            sections.push(scope_name);
+            sections.extend(aliases);
+            next_scope = self.inherits.get(scope_name).map(String::as_str);
+        }
+        sections
+    }
+
+    ///
+    /// Returns the metadata annotated on the given option in its scope's section, if any.
+    ///
+    pub fn option_metadata(&self, id: &OptionId) -> Option<OptionMetadata> {
+        self.metadata
+            .get(&(id.scope.name().to_owned(), ConfigReader::option_name(id)))
+            .cloned()
+    }
+
+    fn origin(&self, section_name: &str, option_name: &str) -> &str {
+        self.origins
+            .get(&(section_name.to_owned(), option_name.to_owned()))
+            .map(String::as_str)
+            .unwrap_or("<unknown>")
+    }
+
+    ///
+    /// Returns the names of all sections in this Config, in sorted order.
+    ///
+    pub fn sections(&self) -> Vec<&str> {
+        self.value
+            .as_table()
+            .map(|table| table.keys().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Checks that each of the `required` sections is present in this Config, such as `GLOBAL` in
+    /// setups that must always configure it, erroring with the names of any that are absent, in
+    /// the order that they were given.
+    ///
+    pub fn require_sections(&self, required: &[&str]) -> Result<(), Vec<String>> {
+        let sections = self.sections();
+        let missing = required
+            .iter()
+            .filter(|section_name| !sections.contains(section_name))
+            .map(|section_name| section_name.to_string())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    ///
+    /// Returns the scalar (i.e. non-array and non-table) value of each option set in the section
+    /// of a scope, keyed by the id of the option, which is reconstructed from its section and
+    /// option name. The values in DEFAULT are not those of any particular scope, and so are
+    /// omitted.
+    ///
+    pub fn as_option_map(&self) -> BTreeMap<OptionId, Value> {
+        let mut option_map = BTreeMap::new();
+        for section_name in self.sections() {
+            if section_name == DEFAULT_SECTION {
+                continue;
+            }
+            for option_name in self.option_names(section_name) {
+                let value = self.get(section_name, option_name).unwrap();
+                if value.is_array() || value.is_table() {
+                    continue;
+                }
+                if let Ok(id) =
+                    OptionId::new(Scope::named(section_name), option_name.split('_'), None)
+                {
+                    option_map.insert(id, value.clone());
+                }
+            }
+        }
+        option_map
+    }
+
+    ///
+    /// Returns the names of the sections for scopes whose names match the glob `pattern`, in
+    /// sorted order, where a `*` in `pattern` matches any (possibly empty) run of characters. For
+    /// example, `python.*` matches `python.test` and `python.lint`, but not `python` itself.
+    ///
+    /// DEFAULT is not the section of a scope, and so is never matched.
+    ///
+    pub fn scopes_matching(&self, pattern: &str) -> Vec<&str> {
+        let pattern_re = Regex::new(&format!(
+            "^{}$",
+            pattern
+                .split('*')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".*")
+        ))
+        .unwrap();
+        self.sections()
+            .into_iter()
+            .filter(|section_name| {
+                *section_name != DEFAULT_SECTION && pattern_re.is_match(section_name)
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns the names of all options set in the given section, in sorted order.
+    ///
+    pub fn option_names(&self, section_name: &str) -> Vec<&str> {
+        self.value
+            .get(section_name)
+            .and_then(Value::as_table)
+            .map(|table| table.keys().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    fn get(&self, section_name: &str, option_name: &str) -> Option<&Value> {
+        self.value
+            .get(section_name)
+            .and_then(|section| section.get(option_name))
+    }
+
+    // The name of the section that the value of `id` is read from: that of its scope (or of a
+    // deprecated alias of its scope, or of a scope it inherits from), falling back to DEFAULT.
+    fn value_section_name<'a>(&'a self, id: &'a OptionId) -> Option<&'a str> {
+        let option_name = ConfigReader::option_name(id);
+        self.scope_sections(id.scope.name())
+            .into_iter()
+            .chain([DEFAULT_SECTION])
+            .find(|section_name| self.get(section_name, &option_name).is_some())
+    }
+
+    fn get_value(&self, id: &OptionId) -> Option<&Value> {
+        self.value_section_name(id)
+            .and_then(|section_name| self.get(section_name, &ConfigReader::option_name(id)))
+    }
+
+    ///
+    /// Returns the options that were added, removed or changed in `other` relative to this Config,
+    /// ordered by section and then option name.
+    ///
+    pub fn diff(&self, other: &Config) -> Vec<ConfigChange> {
+        let mut keys = BTreeSet::new();
+        for config in [self, other] {
+            for section_name in config.sections() {
+                for option_name in config.option_names(section_name) {
+                    keys.insert((section_name, option_name));
+                }
+            }
+        }
+        keys.into_iter()
+            .filter_map(|(section_name, option_name)| {
+                let section = section_name.to_owned();
+                let option = option_name.to_owned();
+                match (
+                    self.get(section_name, option_name),
+                    other.get(section_name, option_name),
+                ) {
+                    (None, Some(value)) => Some(ConfigChange::Added {
+                        section,
+                        option,
+                        value: value.clone(),
+                    }),
+                    (Some(value), None) => Some(ConfigChange::Removed {
+                        section,
+                        option,
+                        value: value.clone(),
+                    }),
+                    (Some(old), Some(new)) if old != new => Some(ConfigChange::Changed {
+                        section,
+                        option,
+                        old: old.clone(),
+                        new: new.clone(),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    ///
+    /// Renders `diff` in a unified-diff-like format for printing in logs: one `+` line per added
+    /// option, one `-` line per removed option, and a `-` then `+` line per changed option.
+    ///
+    pub fn diff_text(&self, other: &Config) -> String {
+        self.diff(other)
+            .into_iter()
+            .map(|change| match change {
+                ConfigChange::Added {
+                    section,
+                    option,
+                    value,
+                } => format!("+ [{section}] {option} = {value}\n"),
+                ConfigChange::Removed {
+                    section,
+                    option,
+                    value,
+                } => format!("- [{section}] {option} = {value}\n"),
+                ConfigChange::Changed {
+                    section,
+                    option,
+                    old,
+                    new,
+                } => format!("- [{section}] {option} = {old}\n+ [{section}] {option} = {new}\n"),
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns a display name for each option set in this Config whose id is not in `read_ids`,
+    /// which is typically the set of ids that were queried over the course of a run.
+    ///
+    /// An option set in the DEFAULT section counts as read if an option of the same name was read
+    /// in any scope. Note that this means DEFAULT values used only for interpolation are reported.
+    ///
+    pub fn unused_options(&self, read_ids: &HashSet<OptionId>) -> Vec<String> {
+        let read_names: HashSet<(&str, String)> = read_ids
+            .iter()
+            .map(|id| (id.scope.name(), ConfigReader::option_name(id)))
+            .collect();
+        let read_default_names: HashSet<&str> =
+            read_names.iter().map(|(_, name)| name.as_str()).collect();
+
+        let mut unused = vec![];
+        for section_name in self.sections() {
+            for option_name in self.option_names(section_name) {
+                let read = if section_name == DEFAULT_SECTION {
+                    read_default_names.contains(option_name)
+                } else {
+                    read_names.contains(&(section_name, option_name.to_owned()))
+                };
+                if !read {
+                    unused.push(format!("[{section_name}] {option_name}"));
+                }
+            }
+        }
+        unused
+    }
+
+    ///
+    /// Returns the display name and value of each option in `defaults` that is set to a value other
+    /// than its default, ordered by display name. A value is found as the getters find it, in the
+    /// sections of the option's scope and then in DEFAULT. This is the config that has actually
+    /// been customized relative to the registered defaults.
+    ///
+    pub fn non_default_options(&self, defaults: &HashMap<OptionId, Value>) -> Vec<(String, Value)> {
+        let mut non_default = defaults
+            .iter()
+            .filter_map(|(id, default)| {
+                self.get_value(id)
+                    .filter(|value| *value != default)
+                    .map(|value| (id.to_string(), value.clone()))
+            })
+            .collect::<Vec<_>>();
+        non_default.sort_by(|(a, _), (b, _)| a.cmp(b));
+        non_default
+    }
+
+    ///
+    /// Generates a starter config file for the options in `defaults`, with a section per scope in
+    /// which each option is set to its default but commented out, and preceded by its `help` (if
+    /// any) as a comment.
+    ///
+    /// Help comments start with `# `, while options are commented out with a bare `#`, so that
+    /// removing the `#` from any option yields a valid config which sets that option.
+    ///
+    pub fn template(
+        defaults: &HashMap<OptionId, Value>,
+        help: &HashMap<OptionId, String>,
+    ) -> String {
+        let mut sections: BTreeMap<&str, BTreeMap<String, &OptionId>> = BTreeMap::new();
+        for id in defaults.keys() {
+            sections
+                .entry(id.scope.name())
+                .or_default()
+                .insert(ConfigReader::option_name(id), id);
+        }
+        sections
+            .into_iter()
+            .map(|(section_name, options)| {
+                let mut lines = vec![format!("[{}]", toml_key(section_name))];
+                for (option_name, id) in options {
+                    lines.push(String::new());
+                    for help_line in help.get(id).into_iter().flat_map(|help| help.lines()) {
+                        lines.push(format!("# {help_line}").trim_end().to_owned());
+                    }
+                    lines.push(format!(
+                        "#{} = {}",
+                        toml_key(&option_name),
+                        inline_toml(&defaults[id])
+                    ));
+                }
+                lines.join("\n") + "\n"
+            })
+            .join("\n")
+    }
+
+    ///
+    /// Returns a Config with every list and dict edit (the `add`, `remove`, `clear` and
+    /// `from_file` tables) resolved into a concrete value, which is the form to use for export,
+    /// hashing and golden tests. Edits to an option in `defaults` are resolved against its
+    /// default, and others against an empty list or dict.
+    ///
+    /// An option in `defaults` that is edited in DEFAULT is resolved into the section of its
+    /// scope, since DEFAULT's edits apply on top of the default of each scope that reads them.
+    ///
+    /// The list of a `from_file` edit is read with `fromfile_expander`, as a `ConfigReader` with
+    /// the same expander would read it, and errors as reading the option would if it can't be.
+    ///
+    pub fn canonicalize(
+        &self,
+        defaults: &HashMap<OptionId, Value>,
+        fromfile_expander: &FromfileExpander,
+    ) -> Result<Config, String> {
+        let mut table = self.value.as_table().cloned().unwrap_or_else(Table::new);
+        let mut origins = self.origins.clone();
+        for (id, default) in defaults {
+            let scope_name = id.scope.name();
+            let option_name = ConfigReader::option_name(id);
+            let values = [DEFAULT_SECTION, scope_name]
+                .into_iter()
+                .filter_map(|section_name| self.get(section_name, &option_name))
+                .collect::<Vec<_>>();
+            if !values.iter().any(|value| is_edit(value)) {
+                continue;
+            }
+            table
+                .entry(scope_name.to_owned())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .unwrap()
+                .insert(
+                    option_name.clone(),
+                    resolve_edit_values(
+                        Some(default),
+                        &values,
+                        &id.to_string(),
+                        fromfile_expander,
+                    )?,
+                );
+            origins
+                .entry((scope_name.to_owned(), option_name.clone()))
+                .or_insert_with(|| self.origin(DEFAULT_SECTION, &option_name).to_owned());
+        }
+        for (section_name, section) in table.iter_mut() {
+            if let Value::Table(section) = section {
+                for (option_name, value) in section.iter_mut() {
+                    if is_edit(value) {
+                        *value = resolve_edit_values(
+                            None,
+                            &[value],
+                            &format!("[{section_name}] {option_name}"),
+                            fromfile_expander,
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(Config {
+            value: Value::Table(table),
+            origins,
+            env: self.env.clone(),
+            scope_aliases: self.scope_aliases.clone(),
+            inherits: self.inherits.clone(),
+            metadata: self.metadata.clone(),
+            locked: self.locked.clone(),
+            locations: self.locations.clone(),
+            warnings: vec![],
+            warning_sink: self.warning_sink.clone(),
+        })
+    }
+
+    ///
+    /// Serializes this Config as compact JSON with the keys of every table in sorted order. This
+    /// is the canonical form that the Rust and Python options implementations agree on, so that
+    /// config read by either may be compared.
+    ///
+    /// TOML values map to the equivalent JSON values, except that:
+    ///   * Datetimes become strings in their TOML (RFC 3339) form, such as `1979-05-27T07:32:00Z`,
+    ///     `1979-05-27` or `07:32:00`, keeping whatever offset and precision they were written with.
+    ///   * Floats that JSON can't represent become the strings `nan`, `inf` and `-inf`.
+    ///
+    pub fn to_canonical_json(&self) -> String {
+        toml_value_to_canonical_json(&self.value).to_string()
+    }
+
+    ///
+    /// Serializes this Config as TOML, casing section names according to `section_case`.
+    ///
+    /// Errors if two sections would be written under the same name once cased.
+    ///
+    pub fn to_toml_string(&self, section_case: SectionCase) -> Result<String, String> {
+        let mut table = Table::new();
+        let mut original_names: HashMap<String, &str> = HashMap::new();
+        if let Some(sections) = self.value.as_table() {
+            for (section_name, section) in sections {
+                let cased_name = section_case.apply(section_name);
+                if let Some(other_name) = original_names.insert(cased_name.clone(), section_name) {
+                    return Err(format!(
+                        "Sections [{other_name}] and [{section_name}] would both be written as \
+                        [{cased_name}]"
+                    ));
+                }
+                table.insert(cased_name, section.clone());
+            }
+        }
+        toml::to_string(&Value::Table(table))
+            .map_err(|e| format!("Failed to serialize config: {e}"))
+    }
+
+    ///
+    /// Returns a new Config with the sections of `other` layered on top of this one. Options set
+    /// in `other` replace the same options set here, while all other options are preserved.
+    ///
+    /// When both set an option to a table of list edits, the edits of `other` apply after those
+    /// set here, as `[DEFAULT]` edits layer under a scope's. When a list value in `other` replaces
+    /// `add`/`remove` edits set here, those edits are discarded, and a warning naming the source
+    /// of each is recorded in the result.
+    ///
+    /// Options listed in the `[_locked]` section of a config, as in
+    /// `options = ['python.pip_version']`, are locked by policy: a later config's values for them
+    /// are ignored, and a warning is recorded for each. Use `try_merge` to error instead.
+    ///
+    pub fn merge(&self, other: &Config) -> Config {
+        let (mut config, locked_overrides) = self.merge_unlocked(other);
+        for message in locked_overrides {
+            config.warn(Severity::Warning, message);
+        }
+        config
+    }
+
+    ///
+    /// Merges `other` on top of this Config as `merge` does, but errors if `other` sets any
+    /// option that is locked by policy.
+    ///
+    pub fn try_merge(&self, other: &Config) -> Result<Config, String> {
+        let (config, locked_overrides) = self.merge_unlocked(other);
+        match locked_overrides.into_iter().next() {
+            Some(message) => Err(message),
+            None => Ok(config),
+        }
+    }
+
+    ///
+    /// Merges `other` on top of this Config as `merge` does, but takes only the options of `other`
+    /// named in `allowed`, as `scope.option`, ignoring the rest. This allows accepting a config,
+    /// such as a remote one, while trusting only specific options from it.
+    ///
+    /// Anything else that `other` declares, such as locked options or scope inheritance, is also
+    /// ignored, since it would affect options beyond those allowed.
+    ///
+    pub fn merge_filtered(&self, other: &Config, allowed: &HashSet<String>) -> Config {
+        let mut filtered = Table::new();
+        for section_name in other.sections() {
+            let section = other
+                .option_names(section_name)
+                .into_iter()
+                .filter(|option_name| allowed.contains(&format!("{section_name}.{option_name}")))
+                .map(|option_name| {
+                    let value = other.get(section_name, option_name).unwrap().clone();
+                    (option_name.to_owned(), value)
+                })
+                .collect::<Table>();
+            if !section.is_empty() {
+                filtered.insert(section_name.to_owned(), Value::Table(section));
+            }
+        }
+        let mut filtered = Config::from_table(filtered, "");
+        for ((section_name, option_name), origin) in filtered.origins.iter_mut() {
+            other.origin(section_name, option_name).clone_into(origin);
+        }
+        self.merge(&filtered)
+    }
+
+    // Merges `other` on top of this Config, except for the options that are locked, returning the
+    // result along with a message for each locked option that `other` attempted to override.
+    fn merge_unlocked(&self, other: &Config) -> (Config, Vec<String>) {
+        let mut merged = self.value.as_table().cloned().unwrap_or_else(Table::new);
+        let mut origins = self.origins.clone();
+        let mut env = self.env.clone();
+        env.extend(other.env.clone());
+        let mut scope_aliases = self.scope_aliases.clone();
+        scope_aliases.extend(other.scope_aliases.clone());
+        let mut inherits = self.inherits.clone();
+        inherits.extend(other.inherits.clone());
+        let mut metadata = self.metadata.clone();
+        metadata.extend(other.metadata.clone());
+        let mut locked = self.locked.clone();
+        locked.extend(other.locked.clone());
+        let mut locations = self.locations.clone();
+        let mut discarded_edits = vec![];
+        let mut locked_overrides = vec![];
+        if let Some(other_table) = other.value.as_table() {
+            for (section_name, section) in other_table {
+                let entry = merged
+                    .entry(section_name.clone())
+                    .or_insert_with(|| Value::Table(Table::new()));
+                match (entry, section) {
+                    (Value::Table(existing), Value::Table(overrides)) => {
+                        for (option_name, value) in overrides {
+                            if self
+                                .locked
+                                .contains(&(section_name.clone(), option_name.clone()))
+                            {
+                                locked_overrides.push(format!(
+                                    "Option [{section_name}] {option_name} is locked by policy \
+                                    and cannot be overridden"
+                                ));
+                                continue;
+                            }
+                            let other_origin = other.origin(section_name, option_name);
+                            let base = existing.get(option_name);
+                            let value = match base
+                                .and_then(|base| concat_list_edit_tables(base, value))
+                            {
+                                Some(edits) => edits,
+                                None => {
+                                    if base.is_some_and(|base| discards_list_edits(base, value)) {
+                                        discarded_edits.push(format!(
+                                            "Replace of [{section_name}] {option_name} in \
+                                            {other_origin} discards list edits from {}",
+                                            self.origin(section_name, option_name)
+                                        ));
+                                    }
+                                    value.clone()
+                                }
+                            };
+                            existing.insert(option_name.clone(), value);
+                            let key = (section_name.clone(), option_name.clone());
+                            match other.locations.get(&key) {
+                                Some(location) => locations.insert(key, location.clone()),
+                                None => locations.remove(&key),
+                            };
+                            origins.insert(
+                                (section_name.clone(), option_name.clone()),
+                                other_origin.to_owned(),
+                            );
+                        }
+                    }
+                    (entry, section) => *entry = section.clone(),
+                }
+            }
+        }
+        let mut config = Config {
+            value: Value::Table(merged),
+            origins,
+            env,
+            scope_aliases,
+            inherits,
+            metadata,
+            locked,
+            locations,
+            warnings: itertools::concat([self.warnings.clone(), other.warnings.clone()]),
+            warning_sink: self
+                .warning_sink
+                .clone()
+                .or_else(|| other.warning_sink.clone()),
+        };
+        for message in discarded_edits {
+            config.warn(Severity::Warning, message);
+        }
+        if let Some(cycle) = inheritance_cycle(&config.inherits) {
+            let message = format!(
+                "Scopes inherit from each other in a cycle: {}",
+                cycle.join(" -> ")
+            );
+            config.warn(Severity::Warning, message);
+        }
+        (config, locked_overrides)
+    }
+
+    ///
+    /// Parses the given TOML `fragment` and merges it on top of this Config, returning the result.
+    ///
+    /// This is a convenience for tests and tooling that want to tweak a handful of values without
+    /// reconstructing a Config from scratch. The fragment is parsed without any seed values, so it
+    /// may only interpolate values it (or its DEFAULT section) defines.
+    ///
+    pub fn overlay(&self, fragment: &str, source_name: &str) -> Result<Config, String> {
+        let overlay =
+            Config::parse_reader(fragment.as_bytes(), source_name, &InterpolationMap::new())?;
+        Ok(self.merge(&overlay))
+    }
+
+    ///
+    /// Applies a JSON object of the form `{"scope": {"option": value}}`, such as overrides
+    /// computed by an orchestration layer, on top of this Config. The patched values take
+    /// precedence over any already set, and JSON values convert to the equivalent TOML values.
+    ///
+    /// A patched value may have a different type than the value it replaces, in which case a
+    /// warning of the change is recorded. Errors, without changing this Config, if the patch is not
+    /// of the expected form, or contains a value with no TOML equivalent, such as a null.
+    ///
+    /// Like the values of a `MapSource`, patched values have no line in a file, and are not
+    /// subject to the options locked by the config.
+    ///
+    pub fn apply_json_patch(&mut self, patch: &serde_json::Value) -> Result<(), String> {
+        let scopes = patch.as_object().ok_or_else(|| {
+            format!("Expected a JSON patch to be an object of scopes but given {patch}")
+        })?;
+        let mut patched_values = vec![];
+        for (section_name, options) in scopes {
+            let options = options.as_object().ok_or_else(|| {
+                format!(
+                    "Expected the JSON patch for [{section_name}] to be an object of options but \
+                    given {options}"
+                )
+            })?;
+            for (option_name, value) in options {
+                let value = json_to_toml_value(value).ok_or_else(|| {
+                    format!(
+                        "The JSON patch for [{section_name}] {option_name} has no TOML \
+                        equivalent: {value}"
+                    )
+                })?;
+                patched_values.push((section_name, option_name, value));
+            }
+        }
+
+        let mut type_changes = vec![];
+        let table = self.value.as_table_mut().unwrap();
+        for (section_name, option_name, value) in patched_values {
+            let section = table
+                .entry(section_name.to_owned())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| format!("Config section [{section_name}] is not a table"))?;
+            if let Some(existing) = section.get(option_name) {
+                if existing.type_str() != value.type_str() {
+                    type_changes.push(format!(
+                        "JSON patch changes the type of [{section_name}] {option_name} from {} \
+                        to {}",
+                        existing.type_str(),
+                        value.type_str()
+                    ));
+                }
+            }
+            section.insert(option_name.to_owned(), value);
+            let key = (section_name.to_owned(), option_name.to_owned());
+            // The patched value has no line, unlike the value of the file that it replaces.
+            self.locations.remove(&key);
+            self.origins.insert(key, "JSON patch".to_owned());
+        }
+        for message in type_changes {
+            self.warn(Severity::Warning, message);
+        }
+        Ok(())
+    }
+
+    ///
+    /// Returns a new Config containing only the options needed to reproduce the values of `ids`,
+    /// within their original sections. For each id this is its value in its scope's section (and
+    /// those of any deprecated aliases of its scope) along with any DEFAULT value of the same
+    /// name, since either may contribute to the value that is read.
+    ///
+    pub fn minimal_for(&self, ids: &[OptionId]) -> Config {
+        let mut table = Table::new();
+        let mut origins = HashMap::new();
+        let mut metadata = HashMap::new();
+        for id in ids {
+            let option_name = ConfigReader::option_name(id);
+            let mut section_names = self.scope_sections(id.scope.name());
+            section_names.push(DEFAULT_SECTION);
+            for section_name in section_names {
+                if let Some(value) = self.get(section_name, &option_name) {
+                    let key = (section_name.to_owned(), option_name.clone());
+                    table
+                        .entry(section_name.to_owned())
+                        .or_insert_with(|| Value::Table(Table::new()))
+                        .as_table_mut()
+                        .unwrap()
+                        .insert(option_name.clone(), value.clone());
+                    if let Some(origin) = self.origins.get(&key) {
+                        origins.insert(key.clone(), origin.clone());
+                    }
+                    if let Some(option_metadata) = self.metadata.get(&key) {
+                        metadata.insert(key, option_metadata.clone());
+                    }
+                }
+            }
+        }
+        Config {
+            value: Value::Table(table),
+            origins,
+            env: self.env.clone(),
+            scope_aliases: self.scope_aliases.clone(),
+            inherits: self.inherits.clone(),
+            metadata,
+            locked: self.locked.clone(),
+            locations: self.locations.clone(),
+            warnings: vec![],
+            warning_sink: self.warning_sink.clone(),
+        }
+    }
+}
+
+pub struct ConfigReader {
+    config: Config,
+    fromfile_expander: FromfileExpander,
+    // How long a `from_command` value's command may run for, or None if they may not be run.
+    command_timeout: Option<Duration>,
+    // Whether relative path values are relative to the directory of the file that set them,
+    // rather than to the build root.
+    paths_relative_to_config: bool,
+    // The profile, such as `ci`, whose `option@profile` list values add to those of `option`.
+    active_profile: Option<String>,
+    // The platform whose values are read from per-platform tables, as named in `PLATFORM_KEYS`.
+    platform: String,
+    // For each option with deprecated values, maps each deprecated value to its replacement.
+    deprecated_values: HashMap<OptionId, HashMap<String, String>>,
+    // The variables that `expr` values may use.
+    variables: HashMap<String, f64>,
+    // The named tables that `lookup` values may select a value from by key.
+    lookup_tables: HashMap<String, HashMap<String, String>>,
+    // Whether list options must be set with array or edit table syntax, rather than with a
+    // string to be parsed as a list.
+    forbid_string_lists: bool,
+}
 
 impl ConfigReader {
     pub fn new(config: Config, fromfile_expander: FromfileExpander) -> Self {
         Self {
             config,
             fromfile_expander,
+            command_timeout: None,
+            paths_relative_to_config: false,
+            active_profile: None,
+            platform: std::env::consts::OS.to_owned(),
+            deprecated_values: HashMap::new(),
+            variables: HashMap::new(),
+            lookup_tables: HashMap::new(),
+            forbid_string_lists: false,
+        }
+    }
+
+    ///
+    /// Provides the variables, such as `num_cores`, that int and float options may use in values
+    /// computed from arithmetic expressions, as in `{expr = "4 * num_cores"}`. Expressions may use
+    /// `+`, `-`, `*`, `/` and parentheses, but no function calls.
+    ///
+    pub fn with_variables(mut self, variables: HashMap<String, f64>) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    ///
+    /// Provides the named tables that scalar options may select a value from by key, as in
+    /// `{lookup = "prod", table = "environments"}`, which resolves to the value of `prod` in the
+    /// `environments` table. This allows config to choose between presets, such as those of each
+    /// deployment environment, with a single key. The value is parsed as it would be if it were
+    /// set directly as a string.
+    ///
+    pub fn with_lookup_tables(
+        mut self,
+        lookup_tables: HashMap<String, HashMap<String, String>>,
+    ) -> Self {
+        self.lookup_tables = lookup_tables;
+        self
+    }
+
+    ///
+    /// Deprecates values of the string option `id`: reading a value that is a key of
+    /// `value_map` returns the value that it maps to instead, and warns of the deprecation. Other
+    /// values are returned as they are. This allows for migrating config from an old value (e.g.
+    /// `gcc`) to a new one (e.g. `clang`) without breaking it.
+    ///
+    /// The warning is reported to the Config's warning sink, or else logged.
+    ///
+    pub fn with_deprecated_values(
+        mut self,
+        id: OptionId,
+        value_map: HashMap<String, String>,
+    ) -> Self {
+        self.deprecated_values.insert(id, value_map);
+        self
+    }
+
+    ///
+    /// Reads the values of per-platform scalar options for `platform` (one of `linux`, `macos` or
+    /// `windows`) rather than for the current platform. This is mainly useful in tests.
+    ///
+    pub fn with_platform(mut self, platform: &str) -> Self {
+        platform.clone_into(&mut self.platform);
+        self
+    }
+
+    ///
+    /// Activates `profile` for list options: an array set for `option@profile` in the same section
+    /// as a list option is added to its value, after the section's own edits to it. For example,
+    /// with the `ci` profile active, `"extra_requirements@ci" = ["pytest-xdist"]` adds
+    /// `pytest-xdist` to `extra_requirements`. Note that the key must be quoted in TOML.
+    ///
+    pub fn with_active_profile(mut self, profile: &str) -> Self {
+        self.active_profile = Some(profile.to_owned());
+        self
+    }
+
+    ///
+    /// Makes `get_path` and `get_path_list` resolve relative paths against the directory of the
+    /// config file that set each value, rather than against the build root. This is for setups
+    /// that merge config files from several roots, where each file's paths are written relative
+    /// to the file itself.
+    ///
+    pub fn with_paths_relative_to_config(mut self) -> Self {
+        self.paths_relative_to_config = true;
+        self
+    }
+
+    ///
+    /// Makes list options error when set to a string, such as `"['a', 'b']"` or `"+['c']"`, rather
+    /// than parsing it as a list or as list edits. This is for repos that standardize on TOML
+    /// array syntax (`option = ["a", "b"]`) and edit tables (`option.add = ["c"]`) everywhere.
+    /// A file may still provide the list, via `option = { from_file = "path" }`.
+    ///
+    pub fn with_string_lists_forbidden(mut self) -> Self {
+        self.forbid_string_lists = true;
+        self
+    }
+
+    ///
+    /// Allows scalar values of the form `{from_command = "..."}` to be resolved by running the
+    /// command and using its trimmed stdout, failing if it exits unsuccessfully or runs for longer
+    /// than `timeout`.
+    ///
+    /// This runs arbitrary commands named by config, so it is off by default, and may only be
+    /// enabled by the embedder: there is deliberately no way for config to enable it itself.
+    ///
+    pub fn allow_commands(mut self, timeout: Duration) -> Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    ///
+    /// Gets the int list option identified by `id` as a set of the items of its resolved list, as
+    /// resolved for any other list option: removals apply after all adds. Useful for options where
+    /// neither order nor duplicates are meaningful.
+    ///
+    pub fn get_int_set(&self, id: &OptionId) -> Result<Option<HashSet<i64>>, String> {
+        Ok(self
+            .get_int_list(id)?
+            .map(|list_edits| resolve_list_edits(list_edits).into_iter().collect()))
+    }
+
+    ///
+    /// Gets the string list option identified by `id`, treating a plain string value as a
+    /// single-element list rather than parsing it as list edit syntax.
+    ///
+    /// This is for the options that historically accept either a scalar or a list: in each
+    /// section, a string becomes a `Replace` of just that string, and anything else is read as a
+    /// string list. The edits of DEFAULT and of the scope's sections layer as for any list option.
+    ///
+    /// Note that a string is taken literally even if it looks like list edit syntax, so
+    /// `"+['a']"` is the single item `+['a']` rather than an add of `a`. Since that is likely a
+    /// mistake, it is warned about.
+    ///
+    pub fn get_string_list_or_scalar(
+        &self,
+        id: &OptionId,
+    ) -> Result<Option<Vec<ListEdit<String>>>, String> {
+        let option_name = Self::option_name(id);
+        self.get_edits(id, |section_name| {
+            match self.get_from_section(section_name, &option_name) {
+                Some(Value::String(value)) => {
+                    if ["[", "+[", "-["]
+                        .iter()
+                        .any(|prefix| value.trim_start().starts_with(prefix))
+                    {
+                        self.warn(format!(
+                            "Value {value:?} for {id} looks like list edit syntax, but is read \
+                            as a single item: use an array or an edit table instead"
+                        ));
+                    }
+                    let expanded = self
+                        .fromfile_expander
+                        .expand(value.to_owned())
+                        .map_err(|e| e.render(self.display(id)))?;
+                    Ok(expanded.map(|item| {
+                        vec![ListEdit {
+                            action: ListEditAction::Replace,
+                            items: vec![item],
+                        }]
+                    }))
+                }
+                _ => self.get_list_from_section::<String>(section_name, id),
+            }
+        })
+    }
+
+    ///
+    /// Gets the string list option identified by `id`, with shell-style brace patterns in each
+    /// item expanded in place, so that `py3{9,10}` yields the two items `py39` and `py310`.
+    ///
+    pub fn get_string_list_expanded(
+        &self,
+        id: &OptionId,
+    ) -> Result<Option<Vec<ListEdit<String>>>, String> {
+        self.get_string_list(id)?
+            .map(|list_edits| {
+                list_edits
+                    .into_iter()
+                    .map(|list_edit| {
+                        let items = list_edit
+                            .items
+                            .iter()
+                            .map(|item| expand_braces(item))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(ListEdit {
+                            action: list_edit.action,
+                            items: items.concat(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+                    .map_err(|e| format!("Failed to expand {id}: {e}"))
+            })
+            .transpose()
+    }
+
+    ///
+    /// Gets the string list option identified by `id` with its list edits applied in order to an
+    /// empty list, erroring on the first resulting element that `pattern` does not match.
+    ///
+    /// Note that `pattern` must be anchored (e.g. `^[a-z_]+$`) to constrain whole elements.
+    ///
+    pub fn get_string_list_matching(
+        &self,
+        id: &OptionId,
+        pattern: &Regex,
+    ) -> Result<Option<Vec<String>>, String> {
+        let list = self.get_string_list(id)?.map(resolve_list_edits);
+        if let Some(items) = &list {
+            if let Some((index, item)) = items
+                .iter()
+                .enumerate()
+                .find(|(_, item)| !pattern.is_match(item))
+            {
+                return Err(format!(
+                    "Expected every element of {id} to match {pattern} but element {index} \
+                    was {item}"
+                ));
+            }
+        }
+        Ok(list)
+    }
+
+    ///
+    /// Gets the string list option identified by `id` as a flat stream of its elements, each
+    /// tagged with the action of the edit that it came from, in the order that the edits apply.
+    /// This is for callers that resolve list edits themselves, and would rather not walk the
+    /// grouped `ListEdit`s that `get_string_list` returns.
+    ///
+    /// Note that an edit with no elements, such as a `Clear`, appears nowhere in the stream.
+    ///
+    pub fn get_string_list_flat(
+        &self,
+        id: &OptionId,
+    ) -> Result<Option<Vec<(ListEditAction, String)>>, String> {
+        Ok(self.get_string_list(id)?.map(|list_edits| {
+            list_edits
+                .into_iter()
+                .flat_map(|list_edit| {
+                    let action = list_edit.action;
+                    list_edit.items.into_iter().map(move |item| (action, item))
+                })
+                .collect()
+        }))
+    }
+
+    ///
+    /// Gets the dict option identified by `id`, erroring if any key of any of its dict edits is
+    /// not matched by `key_pattern`, which defaults to matching valid env var names. This catches
+    /// malformed keys in dicts such as those of subprocess env vars when config is loaded. The
+    /// error names every invalid key, in sorted order.
+    ///
+    /// Note that `key_pattern` must be anchored (e.g. `^[a-z_]+$`) to constrain whole keys.
+    ///
+    /// A string value may reference another value of the dict as `%(.KEY)s`, as in
+    /// `PATH = "%(.BASE)s:/bin"`, which is useful for building up layered values. A reference is
+    /// to the value of KEY in the same dict edit or, failing that, in the dict that the edit
+    /// applies to, as set by earlier edits. A reference to a value's own key is to its value as
+    /// set by earlier edits, as in `PATH.add = { PATH = "%(.PATH)s:/bin" }`. Other references
+    /// that form a cycle are an error.
+    ///
+    pub fn get_string_dict(
+        &self,
+        id: &OptionId,
+        key_pattern: Option<&Regex>,
+    ) -> Result<Option<Vec<DictEdit>>, String> {
+        let key_pattern = key_pattern.unwrap_or(&ENV_VAR_NAME_RE);
+        let mut dict_edits = self.get_dict(id)?;
+        let invalid_keys = dict_edits
+            .iter()
+            .flatten()
+            .flat_map(|dict_edit| dict_edit.items.keys())
+            .filter(|key| !key_pattern.is_match(key))
+            .collect::<BTreeSet<_>>();
+        match invalid_keys.len() {
+            0 => {}
+            1 => {
+                let key = invalid_keys.first().unwrap();
+                return Err(format!("Dict key '{key}' in {id} is invalid"));
+            }
+            _ => {
+                let keys = invalid_keys
+                    .iter()
+                    .map(|key| format!("'{key}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return Err(format!("Dict keys {keys} in {id} are invalid"));
+            }
+        }
+
+        let mut base = HashMap::new();
+        for dict_edit in dict_edits.iter_mut().flatten() {
+            let mut keys = dict_edit.items.keys().cloned().collect::<Vec<_>>();
+            keys.sort();
+            if matches!(dict_edit.action, DictEditAction::Replace) {
+                base.clear();
+            }
+            let mut resolver = DictReferenceResolver {
+                id,
+                items: &dict_edit.items,
+                base: &base,
+                resolved: HashMap::new(),
+                resolving: vec![],
+            };
+            for key in &keys {
+                if matches!(dict_edit.items.get(key), Some(Val::String(_))) {
+                    resolver.resolve(key)?;
+                }
+            }
+            // Only the edit's own values are replaced: those of `base` that it references were
+            // already resolved by their own edits.
+            for (key, value) in resolver.resolved {
+                if let Some(item) = dict_edit.items.get_mut(&key) {
+                    *item = Val::String(value);
+                }
+            }
+            base.extend(dict_edit.items.clone());
+        }
+        Ok(dict_edits)
+    }
+
+    ///
+    /// Gets the option identified by `id` as a set of inclusive int ranges, given as a string in
+    /// the compact notation of, e.g., CPU affinities and port ranges: `"1-3,5,7-9"`.
+    ///
+    pub fn get_int_ranges(
+        &self,
+        id: &OptionId,
+    ) -> Result<Option<Vec<RangeInclusive<i64>>>, String> {
+        self.get_string(id)?
+            .map(|value| parse_int_ranges(&value).map_err(|e| format!("{e} for {id}")))
+            .transpose()
+    }
+
+    ///
+    /// Gets the path option identified by `id`, with a leading `~` expanded to the home directory,
+    /// and a relative path made relative to the build root (or to the directory of the config
+    /// file that set it, see `with_paths_relative_to_config`).
+    ///
+    pub fn get_path(&self, id: &OptionId) -> Result<Option<String>, String> {
+        match self.config.value_section_name(id) {
+            Some(section_name) => Ok(self
+                .get_string(id)?
+                .map(|path| self.expand_path(section_name, &Self::option_name(id), &path))),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// Gets the path list option identified by `id`, where a plain string value is a list of paths
+    /// separated by the OS path separator (`:` on Unix, and `;` on Windows), as in `$PATH`. Such a
+    /// string becomes a `Replace` of its entries, with any empty entries dropped. All other values
+    /// are read as a string list.
+    ///
+    /// In either case, each path is expanded as by `get_path`, relative to the config file of the
+    /// section it was set in, if configured to be.
+    ///
+    pub fn get_path_list(&self, id: &OptionId) -> Result<Option<Vec<ListEdit<String>>>, String> {
+        let option_name = Self::option_name(id);
+        self.get_edits(id, |section_name| {
+            let list_edits = if let Some(Value::String(value)) =
+                self.get_from_section(section_name, &option_name)
+            {
+                Some(vec![ListEdit {
+                    action: ListEditAction::Replace,
+                    items: std::env::split_paths(value)
+                        .filter(|path| !path.as_os_str().is_empty())
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect(),
+                }])
+            } else {
+                self.get_list_from_section::<String>(section_name, id)?
+            };
+            Ok(list_edits.map(|list_edits| {
+                list_edits
+                    .into_iter()
+                    .map(|list_edit| ListEdit {
+                        action: list_edit.action,
+                        items: list_edit
+                            .items
+                            .iter()
+                            .map(|item| self.expand_path(section_name, &option_name, item))
+                            .collect(),
+                    })
+                    .collect()
+            }))
+        })
+    }
+
+    // Expands `path`, the value of `option_name` in `section_name`, for `get_path` and
+    // `get_path_list`.
+    fn expand_path(&self, section_name: &str, option_name: &str, path: &str) -> String {
+        let origin = self
+            .config
+            .origins
+            .get(&(section_name.to_owned(), option_name.to_owned()));
+        let expanded = match origin {
+            // The origin of a value read from a file is that file's path.
+            Some(origin) if self.paths_relative_to_config => {
+                let config_dir = Path::new(origin).parent().unwrap_or(Path::new(""));
+                FromfileExpander::relative_to(BuildRoot::for_path(config_dir.to_path_buf()))
+                    .expand_path(path)
+            }
+            _ => self.fromfile_expander.expand_path(path),
+        };
+        expanded.to_string_lossy().into_owned()
+    }
+
+    ///
+    /// Gets the string option identified by `id` parsed as a semantic version, such as `3.11.4`
+    /// or `2.20.0-rc.1`. Note that all of the major, minor and patch components are required.
+    ///
+    pub fn get_semver(&self, id: &OptionId) -> Result<Option<Version>, String> {
+        self.get_string(id)?
+            .map(|value| {
+                Version::parse(&value).map_err(|e| {
+                    format!("Expected {id} to be a semantic version but given {value}: {e}")
+                })
+            })
+            .transpose()
+    }
+
+    ///
+    /// Checks that the int options `a` and `b` satisfy `rel`, as in `min_workers <= max_workers`.
+    /// The relation is only checked if both options are set.
+    ///
+    pub fn check_relation(&self, a: &OptionId, b: &OptionId, rel: Relation) -> Result<(), String> {
+        if let (Some(x), Some(y)) = (self.get_int(a)?, self.get_int(b)?) {
+            if !rel.holds(x, y) {
+                return Err(format!("Expected {a} {rel} {b} but {a}={x} and {b}={y}"));
+            }
         }
+        Ok(())
+    }
+
+    ///
+    /// Resolves each of the options identified by `ids`, returning how long each took to resolve.
+    /// This identifies the options that are expensive to read, such as those with heavy
+    /// interpolation or with `from_file` or `from_command` values, so that config can be
+    /// optimized.
+    ///
+    /// Each option is resolved by the getter that suits the shape of its value, with the result
+    /// discarded. That includes any side effects of the getter, such as running the commands of
+    /// `from_command` values, but does not otherwise affect the values that getters return.
+    ///
+    pub fn profile_resolution(&self, ids: &[OptionId]) -> Vec<(OptionId, Duration)> {
+        ids.iter()
+            .map(|id| {
+                let start = Instant::now();
+                self.resolve_for_profile(id);
+                (id.clone(), start.elapsed())
+            })
+            .collect()
+    }
+
+    // Resolves the option identified by `id` for `profile_resolution`, with the getter that suits
+    // the shape of its value.
+    fn resolve_for_profile(&self, id: &OptionId) {
+        let is_list = |value: &Value| match value {
+            Value::Array(_) => true,
+            Value::Table(table) => {
+                ["clear", "from_file", "remove"]
+                    .iter()
+                    .any(|key| table.contains_key(*key))
+                    || table.get("add").is_some_and(Value::is_array)
+            }
+            _ => false,
+        };
+        let _ = match self.get_value(id) {
+            Some(Value::Boolean(_)) => self.get_bool(id).map(drop),
+            Some(Value::Integer(_)) => self.get_int(id).map(drop),
+            Some(Value::Float(_)) => self.get_float(id).map(drop),
+            Some(value) if arithmetic_expr(value).is_some() => self.get_float(id).map(drop),
+            Some(value) if is_list(value) => self.get_string_list(id).map(drop),
+            Some(value)
+                if value.is_table()
+                    && from_env_var_name(value).is_none()
+                    && from_command_line(value).is_none()
+                    && lookup_key(value).is_none() =>
+            {
+                self.get_dict(id).map(drop)
+            }
+            _ => self.get_string(id).map(drop),
+        };
     }
 
     fn option_name(id: &OptionId) -> String {
         id.name("_", NameTransform::None)
     }
 
+    // Reports a warning about reading a value to the Config's warning sink, or else logs it.
+    fn warn(&self, message: String) {
+        match &self.config.warning_sink {
+            Some(warning_sink) => warning_sink(Severity::Warning, &message),
+            None => log::warn!("{message}"),
+        }
+    }
+
     fn get_from_section(&self, section_name: &str, option_name: &str) -> Option<&Value> {
         self.config
             .value
@@ -360,28 +3369,92 @@ impl ConfigReader {
             .and_then(|table| table.get(option_name))
     }
 
+    // A scalar option may be given a table of per-platform values, as in
+    // `{linux = "...", macos = "...", default = "..."}`, in which case its value is that for the
+    // current platform, falling back to the `default` (or to no value, if there is no default).
+    // Any other value, including a table without any platform keys, is returned as is, so that
+    // reading it errors about its type.
+    fn platform_value<'a>(
+        &self,
+        id: &OptionId,
+        value: &'a Value,
+    ) -> Result<Option<&'a Value>, String> {
+        match value {
+            Value::Table(table)
+                if from_env_var_name(value).is_none()
+                    && from_command_line(value).is_none()
+                    && arithmetic_expr(value).is_none()
+                    && lookup_key(value).is_none()
+                    && table
+                        .keys()
+                        .any(|key| PLATFORM_KEYS.contains(&key.as_str())) =>
+            {
+                let mut keys = table.keys().collect::<Vec<_>>();
+                keys.sort();
+                if let Some(key) = keys
+                    .into_iter()
+                    .find(|key| !PLATFORM_KEYS.contains(&key.as_str()))
+                {
+                    return Err(format!(
+                        "Unknown platform `{key}` in the value of {id}: expected {}",
+                        render_choice(PLATFORM_KEYS).unwrap()
+                    ));
+                }
+                Ok(table.get(&self.platform).or_else(|| table.get("default")))
+            }
+            value => Ok(Some(value)),
+        }
+    }
+
+    // Evaluates the value of `id` if it is an `{expr = "..."}`, or returns None if it isn't. As
+    // for any scalar, the expression may be the value for the current platform of a per-platform
+    // table.
+    fn get_expr_value(&self, id: &OptionId) -> Result<Option<f64>, String> {
+        let value = match self.get_value(id) {
+            Some(value) => self.platform_value(id, value)?,
+            None => None,
+        };
+        match value.and_then(arithmetic_expr) {
+            Some(expr) => eval_arithmetic(expr, &self.variables)
+                .map(Some)
+                .map_err(|e| format!("{e} in the expression `{expr}` for {id}")),
+            None => Ok(None),
+        }
+    }
+
     fn get_value(&self, id: &OptionId) -> Option<&Value> {
-        let option_name = Self::option_name(id);
-        self.get_from_section(id.scope.name(), &option_name)
-            .or(self.get_from_section(DEFAULT_SECTION, &option_name))
+        self.config.get_value(id)
+    }
+
+    // Collects the edits for `id` from the DEFAULT section, then from the sections of the scopes
+    // that its scope inherits from (furthest ancestor first), then from the sections of any
+    // deprecated aliases of its scope, and finally from its own scope's section, so that the
+    // edits from each section apply on top of those before it.
+    fn get_edits<E>(
+        &self,
+        id: &OptionId,
+        get_from_section: impl Fn(&str) -> Result<Option<Vec<E>>, String>,
+    ) -> Result<Option<Vec<E>>, String> {
+        let mut edits: Option<Vec<E>> = None;
+        let scope_sections = self.config.scope_sections(id.scope.name());
+        for section_name in [DEFAULT_SECTION]
+            .into_iter()
+            .chain(scope_sections.into_iter().rev())
+        {
+            if let Some(section_edits) = get_from_section(section_name)? {
+                edits.get_or_insert_with(Vec::new).extend(section_edits);
+            }
+        }
+        Ok(edits)
     }
 
     fn get_list<T: FromValue + Parseable>(
         &self,
         id: &OptionId,
     ) -> Result<Option<Vec<ListEdit<T>>>, String> {
-        let from_scoped_section_opt = self.get_list_from_section(id.scope.name(), id)?;
-
-        Ok(
-            if let Some(from_default_section) = self.get_list_from_section(DEFAULT_SECTION, id)? {
-                Some(itertools::concat([
-                    from_default_section,
-                    from_scoped_section_opt.unwrap_or(vec![]),
-                ]))
-            } else {
-                from_scoped_section_opt
-            },
-        )
+        self.get_edits(id, |section_name| {
+            self.get_list_from_section(section_name, id)
+        })
     }
 
     fn get_list_from_section<T: FromValue + Parseable>(
@@ -397,15 +3470,54 @@ impl ConfigReader {
                     Value::Table(sub_table) => {
                         if sub_table.is_empty()
                             || !sub_table.keys().collect::<HashSet<_>>().is_subset(
-                                &["add".to_owned(), "remove".to_owned()]
-                                    .iter()
-                                    .collect::<HashSet<_>>(),
+                                &[
+                                    "add".to_owned(),
+                                    "remove".to_owned(),
+                                    "clear".to_owned(),
+                                    "from_file".to_owned(),
+                                ]
+                                .iter()
+                                .collect::<HashSet<_>>(),
                             )
                         {
                             return Err(format!(
-                                "Expected {option_name} to contain an 'add' element, a 'remove' element or both but found: {sub_table:?}"
+                                "Expected {option_name} to contain any of 'clear', 'from_file', 'add' or 'remove' elements but found: {sub_table:?}"
                             ));
                         }
+                        // A clear applies first, so that any adds and removes alongside it are
+                        // applied on top of the emptied list.
+                        if let Some(clear) = sub_table.get("clear") {
+                            match clear.as_bool() {
+                                Some(true) => list_edits.push(ListEdit {
+                                    action: ListEditAction::Clear,
+                                    items: vec![],
+                                }),
+                                Some(false) => (),
+                                None => {
+                                    return Err(format!(
+                                        "Expected {option_name}.clear to be a bool but given \
+                                        {clear}"
+                                    ));
+                                }
+                            }
+                        }
+                        // A `from_file` provides the base list, read as for a `@path` string
+                        // value, that any adds and removes then apply on top of.
+                        if let Some(from_file) = sub_table.get("from_file") {
+                            let path = from_file.as_str().ok_or_else(|| {
+                                format!(
+                                    "Expected {option_name}.from_file to be a path but given \
+                                    {from_file}"
+                                )
+                            })?;
+                            if let Some(es) = self
+                                .fromfile_expander
+                                .expand_to_list::<T>(format!("@{path}"))
+                                .map_err(|e| e.render(self.display(id)))?
+                            {
+                                list_edits.extend(es);
+                            }
+                        }
                         if let Some(add) = sub_table.get("add") {
                             list_edits.push(ListEdit {
                                 action: ListEditAction::Add,
@@ -419,6 +3531,14 @@ impl ConfigReader {
                             });
                         }
                     }
+                    Value::String(_) if self.forbid_string_lists => {
+                        return Err(format!(
+                            "Expected {} to be set with array syntax, as in \
+                            `{option_name} = [...]`, but given the string {value}: string \
+                            list values are forbidden in this repo",
+                            self.display(id)
+                        ));
+                    }
                     Value::String(v) => {
                         if let Some(es) = self
                             .fromfile_expander
@@ -434,6 +3554,15 @@ impl ConfigReader {
                     }),
                 }
             }
+            if let Some(profile) = &self.active_profile {
+                let profile_option_name = format!("{option_name}@{profile}");
+                if let Some(value) = table.get(&profile_option_name) {
+                    list_edits.push(ListEdit {
+                        action: ListEditAction::Add,
+                        items: T::extract_list(&profile_option_name, value)?,
+                    });
+                }
+            }
         }
 
         Ok(if list_edits.is_empty() {
@@ -490,19 +3619,77 @@ impl OptionsSource for ConfigReader {
     }
 
     fn get_string(&self, id: &OptionId) -> Result<Option<String>, String> {
-        String::from_config(self, id)
+        let value = String::from_config(self, id)?;
+        let replacement = value.as_ref().and_then(|value| {
+            self.deprecated_values
+                .get(id)
+                .and_then(|value_map| value_map.get(value))
+        });
+        match (value, replacement) {
+            (Some(value), Some(replacement)) => {
+                self.warn(format!(
+                    "Value '{value}' for {id} is deprecated; using '{replacement}'"
+                ));
+                Ok(Some(replacement.clone()))
+            }
+            (value, _) => Ok(value),
+        }
     }
 
+    // As on the command line, a bool option `foo` may also be set in config by its inversion
+    // `no_foo`. As for any option, a spelling set in the scope's section overrides the other
+    // spelling set in DEFAULT (or in a scope that the scope inherits from), but setting both in
+    // the same section is a conflict.
     fn get_bool(&self, id: &OptionId) -> Result<Option<bool>, String> {
-        bool::from_config(self, id)
+        let negated_id = OptionId {
+            scope: id.scope.clone(),
+            name_components: itertools::concat([vec!["no".to_owned()], id.name_components.clone()]),
+            short_name: None,
+        };
+        // The sections that either spelling may be read from, highest precedence first.
+        let section_names = self
+            .config
+            .scope_sections(id.scope.name())
+            .into_iter()
+            .chain([DEFAULT_SECTION])
+            .collect::<Vec<_>>();
+        let precedence = |id: &OptionId| {
+            let option_name = Self::option_name(id);
+            section_names.iter().position(|section_name| {
+                self.get_from_section(section_name, &option_name).is_some()
+            })
+        };
+        match (precedence(id), precedence(&negated_id)) {
+            (Some(index), Some(negated_index)) if index == negated_index => Err(format!(
+                "Both {} and {} are set; they conflict",
+                Self::option_name(id),
+                Self::option_name(&negated_id)
+            )),
+            (Some(index), Some(negated_index)) if index < negated_index => {
+                bool::from_config(self, id)
+            }
+            (_, Some(_)) => Ok(bool::from_config(self, &negated_id)?.map(|negated| !negated)),
+            (_, None) => bool::from_config(self, id),
+        }
     }
 
     fn get_int(&self, id: &OptionId) -> Result<Option<i64>, String> {
-        i64::from_config(self, id)
+        match self.get_expr_value(id)? {
+            Some(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+                Ok(Some(value as i64))
+            }
+            Some(value) => Err(format!(
+                "Expected {id} to be an int but its expression evaluated to {value}"
+            )),
+            None => i64::from_config(self, id),
+        }
     }
 
     fn get_float(&self, id: &OptionId) -> Result<Option<f64>, String> {
-        f64::from_config(self, id)
+        match self.get_expr_value(id)? {
+            Some(value) => Ok(Some(value)),
+            None => f64::from_config(self, id),
+        }
     }
 
     fn get_bool_list(&self, id: &OptionId) -> Result<Option<Vec<ListEdit<bool>>>, String> {
@@ -522,17 +3709,8 @@ impl OptionsSource for ConfigReader {
     }
 
     fn get_dict(&self, id: &OptionId) -> Result<Option<Vec<DictEdit>>, String> {
-        let from_scoped_section_opt = self.get_dict_from_section(id.scope.name(), id)?;
-
-        Ok(
-            if let Some(from_default_section) = self.get_dict_from_section(DEFAULT_SECTION, id)? {
-                Some(itertools::concat([
-                    from_default_section,
-                    from_scoped_section_opt.unwrap_or(vec![]),
-                ]))
-            } else {
-                from_scoped_section_opt
-            },
-        )
+        self.get_edits(id, |section_name| {
+            self.get_dict_from_section(section_name, id)
+        })
     }
 }
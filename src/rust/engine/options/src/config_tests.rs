@@ -1,22 +1,33 @@
 // Copyright 2021 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use itertools::Itertools;
 use maplit::hashmap;
 use regex::Regex;
-use std::collections::HashMap;
+use semver::Version;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::config::{interpolate_string, ConfigSource};
+use crate::config::{
+    interpolate_string, user_config_paths, ConfigChange, ConfigSource, OptionMetadata, Relation,
+    SectionCase, Severity, UnsetReason,
+};
 use crate::{
-    option_id, DictEdit, DictEditAction, ListEdit, ListEditAction, OptionId, OptionsSource, Val,
+    option_id, DictEdit, DictEditAction, ListEdit, ListEditAction, OptionId, OptionsSource, Scope,
+    Val,
 };
 
 use crate::config::{Config, ConfigReader};
 use crate::fromfile::test_util::write_fromfile;
 use crate::fromfile::FromfileExpander;
 use tempfile::TempDir;
+use toml::value::Table;
+use toml::Value;
 
 fn maybe_config(file_content: &str) -> Result<ConfigReader, String> {
     let dir = TempDir::new().unwrap();
@@ -39,6 +50,17 @@ fn config(file_content: &str) -> ConfigReader {
     maybe_config(file_content).unwrap()
 }
 
+fn parsed_config(content: &str) -> Config {
+    Config::parse(
+        &ConfigSource {
+            path: PathBuf::from("pants.toml"),
+            content: content.to_string(),
+        },
+        &HashMap::new(),
+    )
+    .unwrap()
+}
+
 #[test]
 fn test_display() {
     let config = config("");
@@ -66,7 +88,7 @@ fn test_interpolate_string() {
             .iter()
             .map(|(k, v)| (k.to_string(), v.to_string()))
             .collect();
-        interpolate_string(template.to_string(), &interpolation_map)
+        interpolate_string(template.to_string(), &interpolation_map, None)
     }
 
     let template = "%(greeting)s world, what's your %(thing)s?";
@@ -99,6 +121,18 @@ fn test_interpolate_string() {
         "Hello world, what's your real name?",
         interp(template, replacements).unwrap()
     );
+
+    let template = "%(a)s and %(c)s";
+    let replacements = vec![("a", "%(b)s"), ("b", "x%(c)s"), ("c", "%(a)s")];
+    assert_eq!(
+        "Placeholders refer to each other in a cycle: a -> b -> c -> a",
+        interp(template, replacements).unwrap_err()
+    );
+
+    // The same placeholder may be used more than once, as long as it doesn't refer to itself.
+    let template = "%(a)s %(a)s";
+    let replacements = vec![("a", "%(b)s%(b)s"), ("b", "x")];
+    assert_eq!("xx xx", interp(template, replacements).unwrap());
 }
 
 #[test]
@@ -353,6 +387,180 @@ fn test_list_fromfile() {
     );
 }
 
+#[test]
+fn test_arithmetic_exprs() {
+    let conf = config(
+        "[GLOBAL]\n\
+         jobs = { expr = '4 * num_cores' }\n\
+         workers = { expr = '(num_cores - 2) / 2' }\n\
+         ratio = { expr = 'num_cores / 4' }\n\
+         unknown = { expr = '2 * num_gpus' }\n\
+         div_zero = { expr = 'num_cores / (4 - 4)' }\n\
+         per_platform = { linux = { expr = '2 * num_cores' }, default = 1 }\n",
+    )
+    .with_variables(HashMap::from([("num_cores".to_string(), 6.0)]));
+
+    assert_eq!(Ok(Some(24)), conf.get_int(&option_id!("jobs")));
+    assert_eq!(Ok(Some(2)), conf.get_int(&option_id!("workers")));
+    assert_eq!(Ok(Some(1.5)), conf.get_float(&option_id!("ratio")));
+    assert_eq!(
+        Err("Expected [GLOBAL] ratio to be an int but its expression evaluated to 1.5".to_string()),
+        conf.get_int(&option_id!("ratio"))
+    );
+    assert_eq!(
+        Err(
+            "Unknown variable `num_gpus` in the expression `2 * num_gpus` for [GLOBAL] unknown"
+                .to_string()
+        ),
+        conf.get_int(&option_id!("unknown"))
+    );
+    assert_eq!(
+        Err(
+            "Division by zero in the expression `num_cores / (4 - 4)` for [GLOBAL] div_zero"
+                .to_string()
+        ),
+        conf.get_float(&option_id!("div_zero"))
+    );
+    // An expression may be the value for a platform.
+    let conf = conf.with_platform("linux");
+    assert_eq!(Ok(Some(12)), conf.get_int(&option_id!("per_platform")));
+    assert_eq!(Ok(Some(12.0)), conf.get_float(&option_id!("per_platform")));
+    let conf = conf.with_platform("macos");
+    assert_eq!(Ok(Some(1)), conf.get_int(&option_id!("per_platform")));
+}
+
+#[test]
+fn test_lookup_values() {
+    let conf = config(
+        "[GLOBAL]\n\
+         url = { lookup = 'prod', table = 'environments' }\n\
+         replicas = { lookup = 'prod', table = 'replicas' }\n\
+         missing_key = { lookup = 'qa', table = 'environments' }\n\
+         missing_table = { lookup = 'prod', table = 'regions' }\n",
+    )
+    .with_lookup_tables(HashMap::from([
+        (
+            "environments".to_string(),
+            HashMap::from([
+                ("dev".to_string(), "https://dev.example.com".to_string()),
+                ("prod".to_string(), "https://example.com".to_string()),
+            ]),
+        ),
+        (
+            "replicas".to_string(),
+            HashMap::from([("prod".to_string(), "3".to_string())]),
+        ),
+    ]));
+
+    assert_eq!(
+        Ok(Some("https://example.com".to_string())),
+        conf.get_string(&option_id!("url"))
+    );
+    assert_eq!(Ok(Some(3)), conf.get_int(&option_id!("replicas")));
+    assert_eq!(
+        Err(
+            "Expected lookup table `environments` to contain the key `qa` for [GLOBAL] \
+            missing_key (via lookup)"
+                .to_string()
+        ),
+        conf.get_string(&option_id!("missing_key"))
+    );
+    assert_eq!(
+        Err("Unknown lookup table `regions` for [GLOBAL] missing_table (via lookup)".to_string()),
+        conf.get_string(&option_id!("missing_table"))
+    );
+}
+
+#[test]
+fn test_list_from_file_with_edits() {
+    let (_tmpdir, fromfile_path) = write_fromfile("tags.json", "[\"a\", \"b\", \"c\"]");
+    let conf = config(&format!(
+        "[GLOBAL]\n\
+         tags = {{ from_file = '{}', add = ['d'], remove = ['b'] }}\n\
+         bad = {{ from_file = 1 }}\n",
+        fromfile_path.display()
+    ));
+
+    assert_eq!(
+        Ok(Some(vec![
+            ListEdit {
+                action: ListEditAction::Replace,
+                items: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            },
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["d".to_string()],
+            },
+            ListEdit {
+                action: ListEditAction::Remove,
+                items: vec!["b".to_string()],
+            },
+        ])),
+        conf.get_string_list(&option_id!("tags"))
+    );
+    assert_eq!(
+        Err("Expected bad.from_file to be a path but given 1".to_string()),
+        conf.get_string_list(&option_id!("bad"))
+    );
+}
+
+#[test]
+fn test_string_lists_forbidden() {
+    let content = "[GLOBAL]\n\
+                   parsed = \"['a', 'b']\"\n\
+                   edited = \"+['c']\"\n\
+                   array = ['a', 'b']\n\
+                   table.add = ['c']\n";
+    let replace = |items: &[&str]| {
+        Ok(Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: items.iter().map(|item| item.to_string()).collect(),
+        }]))
+    };
+
+    let conf = config(content);
+    assert_eq!(
+        replace(&["a", "b"]),
+        conf.get_string_list(&option_id!("parsed"))
+    );
+    assert_eq!(
+        Ok(Some(vec![ListEdit {
+            action: ListEditAction::Add,
+            items: vec!["c".to_string()],
+        }])),
+        conf.get_string_list(&option_id!("edited"))
+    );
+
+    let conf = config(content).with_string_lists_forbidden();
+    assert_eq!(
+        Err(
+            "Expected [GLOBAL] parsed to be set with array syntax, as in `parsed = [...]`, but \
+            given the string \"['a', 'b']\": string list values are forbidden in this repo"
+                .to_string()
+        ),
+        conf.get_string_list(&option_id!("parsed"))
+    );
+    assert_eq!(
+        Err(
+            "Expected [GLOBAL] edited to be set with array syntax, as in `edited = [...]`, but \
+            given the string \"+['c']\": string list values are forbidden in this repo"
+                .to_string()
+        ),
+        conf.get_string_list(&option_id!("edited"))
+    );
+    assert_eq!(
+        replace(&["a", "b"]),
+        conf.get_string_list(&option_id!("array"))
+    );
+    assert_eq!(
+        Ok(Some(vec![ListEdit {
+            action: ListEditAction::Add,
+            items: vec!["c".to_string()],
+        }])),
+        conf.get_string_list(&option_id!("table"))
+    );
+}
+
 #[test]
 fn test_dict_fromfile() {
     fn do_test(content: &str, filename: &str) {
@@ -410,3 +618,2598 @@ fn test_nonexistent_optional_fromfile() {
     let conf = config("[GLOBAL]\nfoo = '@?/does/not/exist'\n");
     assert!(conf.get_string(&option_id!("foo")).unwrap().is_none());
 }
+
+#[test]
+fn test_overlay() {
+    let base = parsed_config("[python]\nfoo = 'a'\nbar = 'c'\n[go]\nfoo = 'd'\n");
+    let overlaid = base.overlay("[python]\nfoo = 'b'\n", "overlay").unwrap();
+
+    let conf = ConfigReader::new(overlaid, FromfileExpander::relative_to_cwd());
+    assert_eq!(
+        Some("b".to_string()),
+        conf.get_string(&option_id!(["python"], "foo")).unwrap()
+    );
+    assert_eq!(
+        Some("c".to_string()),
+        conf.get_string(&option_id!(["python"], "bar")).unwrap()
+    );
+    assert_eq!(
+        Some("d".to_string()),
+        conf.get_string(&option_id!(["go"], "foo")).unwrap()
+    );
+
+    let err = base.overlay("[python\n", "overlay").err().unwrap();
+    assert!(err.starts_with("Failed to parse config file overlay: "));
+}
+
+#[test]
+fn test_unused_options() {
+    let conf = parsed_config(
+        "[DEFAULT]\n\
+     shared = 1\n\
+     unread = 2\n\
+     [python]\n\
+     foo = 'a'\n\
+     bar = 'b'\n\
+     [go]\n\
+     baz = 1\n",
+    );
+    let read_ids = HashSet::from([
+        option_id!(["python"], "foo"),
+        option_id!(["go"], "shared"),
+        option_id!(["go"], "never_set"),
+    ]);
+    assert_eq!(
+        vec![
+            "[DEFAULT] unread".to_string(),
+            "[go] baz".to_string(),
+            "[python] bar".to_string(),
+        ],
+        conf.unused_options(&read_ids)
+    );
+}
+
+#[test]
+fn test_to_canonical_json() {
+    let conf = parsed_config(
+        "[b]\n\
+         z = 1\n\
+         a = 'x'\n\
+         [a]\n\
+         when = 1979-05-27T07:32:00Z\n\
+         day = 1979-05-27\n\
+         at = 07:32:00\n\
+         ratio = 2.5\n\
+         limit = inf\n\
+         nested = { y = true, x = [1, 2] }\n",
+    );
+    let expected = "{\"a\":{\
+                    \"at\":\"07:32:00\",\
+                    \"day\":\"1979-05-27\",\
+                    \"limit\":\"inf\",\
+                    \"nested\":{\"x\":[1,2],\"y\":true},\
+                    \"ratio\":2.5,\
+                    \"when\":\"1979-05-27T07:32:00Z\"},\
+                    \"b\":{\"a\":\"x\",\"z\":1}}";
+    assert_eq!(expected, conf.to_canonical_json());
+
+    // The same config written in another order has the same canonical form.
+    let reordered = parsed_config(
+        "[a]\n\
+         nested = { x = [1, 2], y = true }\n\
+         limit = inf\n\
+         ratio = 2.5\n\
+         at = 07:32:00\n\
+         day = 1979-05-27\n\
+         when = 1979-05-27T07:32:00Z\n\
+         [b]\n\
+         a = 'x'\n\
+         z = 1\n",
+    );
+    assert_eq!(expected, reordered.to_canonical_json());
+}
+
+#[test]
+fn test_to_toml_string_section_case() {
+    let conf = parsed_config("[global]\nbar = 1\n[Python]\nfoo = 'a'\n");
+
+    let lowercased = conf.to_toml_string(SectionCase::Lowercase).unwrap();
+    assert!(lowercased.contains("[python]\n"));
+    assert!(!lowercased.contains("[Python]"));
+    assert_eq!(
+        vec!["global", "python"],
+        parsed_config(&lowercased).sections()
+    );
+
+    let preserved = conf.to_toml_string(SectionCase::Preserve).unwrap();
+    assert_eq!(
+        vec!["Python", "global"],
+        parsed_config(&preserved).sections()
+    );
+
+    let canonical = conf.to_toml_string(SectionCase::Canonical).unwrap();
+    assert_eq!(
+        vec!["GLOBAL", "python"],
+        parsed_config(&canonical).sections()
+    );
+
+    let colliding = parsed_config("[python]\nfoo = 'a'\n[Python]\nfoo = 'b'\n");
+    assert_eq!(
+        "Sections [Python] and [python] would both be written as [python]",
+        colliding
+            .to_toml_string(SectionCase::Lowercase)
+            .unwrap_err()
+    );
+}
+
+#[test]
+fn test_get_int_set() {
+    let conf = config(
+        "[DEFAULT]\n\
+     codes = [1, 2, 2]\n\
+     [scope]\n\
+     codes.add = [3, 3, 4]\n\
+     codes.remove = [1]\n\
+     [other]\n\
+     codes = [5, 5, 6, 5]\n\
+     readded = \"+[1],-[1],+[1]\"\n\
+     bad_codes = [1, 'two']\n",
+    );
+    assert_eq!(
+        Some(HashSet::from([2, 3, 4])),
+        conf.get_int_set(&option_id!(["scope"], "codes")).unwrap()
+    );
+    assert_eq!(
+        Some(HashSet::from([5, 6])),
+        conf.get_int_set(&option_id!(["other"], "codes")).unwrap()
+    );
+    // As for any list option, the removal applies after all the adds.
+    assert_eq!(
+        Some(HashSet::new()),
+        conf.get_int_set(&option_id!(["other"], "readded")).unwrap()
+    );
+    assert_eq!(
+        None,
+        conf.get_int_set(&option_id!(["other"], "unset")).unwrap()
+    );
+
+    let err = conf
+        .get_int_set(&option_id!(["other"], "bad_codes"))
+        .unwrap_err();
+    assert!(
+        err.contains("containing non-int item \"two\""),
+        "Unexpected error: {err}"
+    );
+}
+
+#[test]
+fn test_get_string_list_or_scalar() {
+    let reported = Arc::new(Mutex::new(vec![]));
+    let mut conf = parsed_config(
+        "[DEFAULT]\n\
+     tags.add = ['d']\n\
+     [scalar]\n\
+     foo = '+[\"x\"]'\n\
+     tags = 'x'\n\
+     [list]\n\
+     foo = ['x', 'y']\n\
+     [edits]\n\
+     foo.add = ['z']\n",
+    );
+    conf.set_warning_sink({
+        let reported = reported.clone();
+        Arc::new(move |_, message| reported.lock().unwrap().push(message.to_string()))
+    });
+    let conf = ConfigReader::new(conf, FromfileExpander::relative_to_cwd());
+
+    // A string in list edit syntax is still a single item, but is warned about.
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec!["+[\"x\"]".to_string()],
+        }]),
+        conf.get_string_list_or_scalar(&option_id!(["scalar"], "foo"))
+            .unwrap()
+    );
+    assert_eq!(
+        vec![
+            "Value \"+[\\\"x\\\"]\" for [scalar] foo looks like list edit syntax, but is read as \
+             a single item: use an array or an edit table instead"
+                .to_string()
+        ],
+        *reported.lock().unwrap()
+    );
+    // A scalar in the scope's section layers over the edits of DEFAULT.
+    assert_eq!(
+        Some(vec![
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["d".to_string()],
+            },
+            ListEdit {
+                action: ListEditAction::Replace,
+                items: vec!["x".to_string()],
+            },
+        ]),
+        conf.get_string_list_or_scalar(&option_id!(["scalar"], "tags"))
+            .unwrap()
+    );
+    assert_eq!(1, reported.lock().unwrap().len());
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec!["x".to_string(), "y".to_string()],
+        }]),
+        conf.get_string_list_or_scalar(&option_id!(["list"], "foo"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Add,
+            items: vec!["z".to_string()],
+        }]),
+        conf.get_string_list_or_scalar(&option_id!(["edits"], "foo"))
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        conf.get_string_list_or_scalar(&option_id!(["list"], "bar"))
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_apply_json_patch() {
+    let mut conf = parsed_config(
+        "[python]\n\
+         pip_version = '23.1'\n\
+         jobs = '4'\n\
+         resolver = 'pip'\n",
+    );
+    conf.apply_json_patch(&serde_json::json!({
+        "python": {
+            "jobs": 8,
+            "pip_version": "24.0",
+            "constraints": [">=3.9", ">=3.11"],
+        },
+        "pytest": {
+            "env_vars": {"CI": "1"},
+            "ratio": 0.5,
+            "verbose": true,
+        },
+    }))
+    .unwrap();
+
+    let expected = parsed_config(
+        "[python]\n\
+         pip_version = '24.0'\n\
+         jobs = 8\n\
+         resolver = 'pip'\n\
+         constraints = ['>=3.9', '>=3.11']\n\
+         [pytest]\n\
+         env_vars = { CI = '1' }\n\
+         ratio = 0.5\n\
+         verbose = true\n",
+    );
+    assert_eq!(Vec::<ConfigChange>::new(), conf.diff(&expected));
+    assert_eq!(
+        vec!["JSON patch changes the type of [python] jobs from string to integer"],
+        conf.warnings()
+    );
+
+    let err = conf
+        .apply_json_patch(&serde_json::json!({"python": {"jobs": 2, "resolver": null}}))
+        .unwrap_err();
+    assert_eq!(
+        "The JSON patch for [python] resolver has no TOML equivalent: null",
+        err
+    );
+    // A patch that fails to apply changes nothing.
+    assert_eq!(Vec::<ConfigChange>::new(), conf.diff(&expected));
+    assert_eq!(
+        "Expected the JSON patch for [python] to be an object of options but given 1",
+        conf.apply_json_patch(&serde_json::json!({"python": 1}))
+            .unwrap_err()
+    );
+
+    // Lints of a patched value don't refer to the line of the value that it replaced.
+    let mut conf = parsed_config("[python]\ntags = ['a', 'a']\n");
+    conf.apply_json_patch(&serde_json::json!({"python": {"tags": ["b", "b"]}}))
+        .unwrap();
+    assert_eq!(
+        vec!["JSON patch: [python] tags: \"b\" is listed more than once"],
+        conf.lint()
+            .iter()
+            .map(|lint| lint.to_string())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_merge_locked_options() {
+    let base = parsed_config(
+        "[_locked]\n\
+         options = ['python.pip_version', 'GLOBAL.level']\n\
+         [python]\n\
+         pip_version = '23.1'\n",
+    );
+    let downstream = parsed_config(
+        "[python]\n\
+         pip_version = '24.0'\n\
+         resolver = 'pip'\n",
+    );
+    assert_eq!(vec!["python"], base.sections());
+
+    let merged = base.merge(&downstream);
+    let conf = ConfigReader::new(merged.clone(), FromfileExpander::relative_to_cwd());
+    assert_eq!(
+        Some("23.1".to_string()),
+        conf.get_string(&option_id!(["python"], "pip", "version"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some("pip".to_string()),
+        conf.get_string(&option_id!(["python"], "resolver"))
+            .unwrap()
+    );
+    assert_eq!(
+        vec!["Option [python] pip_version is locked by policy and cannot be overridden"],
+        merged.warnings()
+    );
+
+    // The lock persists through later merges, even of options that the base config didn't set.
+    let merged = merged.merge(&parsed_config("[GLOBAL]\nlevel = 'debug'\n"));
+    assert_eq!(Vec::<&str>::new(), merged.option_names("GLOBAL"));
+
+    assert_eq!(
+        "Option [python] pip_version is locked by policy and cannot be overridden",
+        base.try_merge(&downstream).err().unwrap()
+    );
+    assert!(base
+        .try_merge(&parsed_config("[python]\nresolver = 'pip'\n"))
+        .is_ok());
+
+    assert_eq!(
+        "Expected [_locked] to contain just an `options` list of `scope.option` strings in \
+         config file pants.toml",
+        Config::parse_reader(
+            Cursor::new("[_locked]\noptions = ['pip_version']\n"),
+            "pants.toml",
+            &HashMap::new()
+        )
+        .err()
+        .unwrap()
+    );
+}
+
+#[test]
+fn test_merge_warns_on_discarded_list_edits() {
+    let base = parsed_config(
+        "[python]\n\
+     extra.add = ['a']\n\
+     other.add = ['b']\n\
+     scalar = 'c'\n\
+     tags = \"+['g'],-['h']\"\n\
+     env = { add = ['i'] }\n",
+    );
+    let merged = base
+        .overlay(
+            "[python]\n\
+     extra = ['d']\n\
+     other.add = ['e']\n\
+     scalar = 'f'\n\
+     tags = ['j']\n\
+     env = \"+['k']\"\n",
+            "overlay.toml",
+        )
+        .unwrap();
+    assert!(base.warnings().is_empty());
+    assert_eq!(
+        vec![
+            "Replace of [python] env in overlay.toml discards list edits from pants.toml"
+                .to_string(),
+            "Replace of [python] extra in overlay.toml discards list edits from pants.toml"
+                .to_string(),
+            "Replace of [python] tags in overlay.toml discards list edits from pants.toml"
+                .to_string(),
+        ],
+        merged.warnings()
+    );
+
+    // A dict value is not a list edit, so replacing it discards nothing.
+    let merged = parsed_config(
+        "[subprocess]\nenv_vars = { add = { A = '1' } }\n[python]\nenv = { A = '1' }\n",
+    )
+    .overlay(
+        "[subprocess]\nenv_vars = ['A=2']\n[python]\nenv = ['A=2']\n",
+        "overlay.toml",
+    )
+    .unwrap();
+    assert!(merged.warnings().is_empty());
+}
+
+#[test]
+fn test_merge_concatenates_list_edits() {
+    let base = parsed_config(
+        "[python]\n\
+     extra.add = ['a', 'b']\n\
+     extra.remove = ['c']\n\
+     other.add = ['d']\n",
+    );
+    let merged = base
+        .overlay(
+            "[python]\n\
+     extra.add = ['e']\n\
+     extra.remove = ['a']\n\
+     other = { clear = true, add = ['f'] }\n",
+            "overlay.toml",
+        )
+        .unwrap();
+    assert!(merged.warnings().is_empty());
+
+    let conf = ConfigReader::new(merged, FromfileExpander::relative_to_cwd());
+    assert_eq!(
+        Some(vec![
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["a".to_string(), "b".to_string(), "e".to_string()]
+            },
+            ListEdit {
+                action: ListEditAction::Remove,
+                items: vec!["c".to_string(), "a".to_string()]
+            },
+        ]),
+        conf.get_string_list(&option_id!(["python"], "extra"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some(vec![
+            ListEdit {
+                action: ListEditAction::Clear,
+                items: vec![]
+            },
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["f".to_string()]
+            },
+        ]),
+        conf.get_string_list(&option_id!(["python"], "other"))
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_from_file_max_bytes() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("pants.toml");
+    File::create(&path)
+        .unwrap()
+        .write_all("[a]\nb = 'c'\n".as_bytes())
+        .unwrap();
+
+    assert!(ConfigSource::from_file_with_max_bytes(&path, 12).is_ok());
+    let err = ConfigSource::from_file_with_max_bytes(&path, 11).unwrap_err();
+    assert_eq!(
+        format!(
+            "Config file {} exceeds maximum size of 11 bytes",
+            path.display()
+        ),
+        err
+    );
+}
+
+#[test]
+fn test_get_semver() {
+    let conf = config(
+        "[python]\n\
+     version = '3.11.4'\n\
+     pre = '2.20.0-rc.1'\n\
+     bad = '3.11'\n",
+    );
+    assert_eq!(
+        Some(Version::new(3, 11, 4)),
+        conf.get_semver(&option_id!(["python"], "version")).unwrap()
+    );
+
+    let pre = conf
+        .get_semver(&option_id!(["python"], "pre"))
+        .unwrap()
+        .unwrap();
+    assert_eq!((2, 20, 0), (pre.major, pre.minor, pre.patch));
+    assert_eq!("rc.1", pre.pre.as_str());
+    assert!(pre < Version::new(2, 20, 0));
+
+    let err = conf.get_semver(&option_id!(["python"], "bad")).unwrap_err();
+    assert!(
+        err.starts_with("Expected [python] bad to be a semantic version but given 3.11: "),
+        "Unexpected error: {err}"
+    );
+    assert_eq!(
+        None,
+        conf.get_semver(&option_id!(["python"], "unset")).unwrap()
+    );
+}
+
+#[test]
+fn test_list_clear() {
+    let conf = config(
+        "[DEFAULT]\n\
+     foo = ['a']\n\
+     [scope]\n\
+     foo.clear = true\n\
+     foo.add = ['b']\n\
+     [noop]\n\
+     foo.clear = false\n\
+     foo.add = ['c']\n\
+     [bad]\n\
+     foo.clear = 'yes'\n",
+    );
+    assert_eq!(
+        vec![
+            ListEdit {
+                action: ListEditAction::Replace,
+                items: vec!["a".to_string()],
+            },
+            ListEdit {
+                action: ListEditAction::Clear,
+                items: vec![],
+            },
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["b".to_string()],
+            },
+        ],
+        conf.get_string_list(&option_id!(["scope"], "foo"))
+            .unwrap()
+            .unwrap()
+    );
+    assert_eq!(
+        vec![
+            ListEdit {
+                action: ListEditAction::Replace,
+                items: vec!["a".to_string()],
+            },
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["c".to_string()],
+            },
+        ],
+        conf.get_string_list(&option_id!(["noop"], "foo"))
+            .unwrap()
+            .unwrap()
+    );
+    assert_eq!(
+        "Expected foo.clear to be a bool but given \"yes\"",
+        conf.get_string_list(&option_id!(["bad"], "foo"))
+            .unwrap_err()
+    );
+}
+
+#[test]
+fn test_parse_reader() {
+    let seed_values = HashMap::from([("seed".to_string(), "seedval".to_string())]);
+    let conf =
+        Config::parse_reader(Cursor::new("[a]\nb = '%(seed)s'\n"), "stream", &seed_values).unwrap();
+    assert_eq!(
+        Some("seedval".to_string()),
+        ConfigReader::new(conf, FromfileExpander::relative_to_cwd())
+            .get_string(&option_id!(["a"], "b"))
+            .unwrap()
+    );
+
+    let err = Config::parse_reader(Cursor::new("[a"), "stream", &seed_values)
+        .err()
+        .unwrap();
+    assert!(
+        err.starts_with("Failed to parse config file stream: "),
+        "Unexpected error: {err}"
+    );
+}
+
+#[test]
+fn test_from_env() {
+    let seed_values = HashMap::from([
+        ("env.TOKEN".to_string(), "secret".to_string()),
+        ("env.NUM".to_string(), "42".to_string()),
+    ]);
+    let conf = ConfigReader::new(
+        Config::parse_reader(
+            Cursor::new(
+                "[a]\n\
+             token = { from_env = 'TOKEN' }\n\
+             num = { from_env = 'NUM' }\n\
+             missing = { from_env = 'NOPE' }\n",
+            ),
+            "pants.toml",
+            &seed_values,
+        )
+        .unwrap(),
+        FromfileExpander::relative_to_cwd(),
+    );
+    assert_eq!(
+        Some("secret".to_string()),
+        conf.get_string(&option_id!(["a"], "token")).unwrap()
+    );
+    assert_eq!(Some(42), conf.get_int(&option_id!(["a"], "num")).unwrap());
+    assert_eq!(
+        "Expected env var NOPE to be set for [a] missing (via from_env)",
+        conf.get_string(&option_id!(["a"], "missing")).unwrap_err()
+    );
+}
+
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn test_from_command() {
+    let content = "[a]\n\
+                   rev = { from_command = 'echo \"  abc123  \"' }\n\
+                   num = { from_command = 'echo 42' }\n\
+                   failing = { from_command = 'exit 3' }\n\
+                   slow = { from_command = 'sleep 5' }\n";
+    let conf = config(content).allow_commands(Duration::from_millis(500));
+    assert_eq!(
+        Some("abc123".to_string()),
+        conf.get_string(&option_id!(["a"], "rev")).unwrap()
+    );
+    assert_eq!(Some(42), conf.get_int(&option_id!(["a"], "num")).unwrap());
+    assert_eq!(
+        "Command `exit 3` failed with exit status: 3 for [a] failing (via from_command)",
+        conf.get_string(&option_id!(["a"], "failing")).unwrap_err()
+    );
+    assert_eq!(
+        "Command `sleep 5` timed out after 500ms for [a] slow (via from_command)",
+        conf.get_string(&option_id!(["a"], "slow")).unwrap_err()
+    );
+
+    // Commands are never run unless the embedder allows them.
+    let conf = config(content);
+    assert_eq!(
+        "Cannot run `echo 42` for [a] num (via from_command): running commands from config is \
+         not enabled",
+        conf.get_int(&option_id!(["a"], "num")).unwrap_err()
+    );
+}
+
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn test_profile_resolution() {
+    let conf = config(
+        "[a]\n\
+         name = '%(seed1)s-name'\n\
+         jobs = 4\n\
+         tags = ['x', 'y']\n\
+         env = { LANG = 'C' }\n\
+         slow = { from_command = 'sleep 0.2 && echo done' }\n",
+    )
+    .allow_commands(Duration::from_secs(5));
+    let ids = [
+        option_id!(["a"], "name"),
+        option_id!(["a"], "jobs"),
+        option_id!(["a"], "tags"),
+        option_id!(["a"], "env"),
+        option_id!(["a"], "slow"),
+        option_id!(["a"], "missing"),
+    ];
+
+    let profile = conf.profile_resolution(&ids);
+    assert_eq!(
+        ids.to_vec(),
+        profile.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>()
+    );
+    assert!(profile[4].1 >= Duration::from_millis(200));
+
+    // Profiling doesn't alter the values that the getters return.
+    assert_eq!(
+        Ok(Some("seed1val-name".to_string())),
+        conf.get_string(&ids[0])
+    );
+    assert_eq!(Ok(Some(4)), conf.get_int(&ids[1]));
+    assert_eq!(
+        Ok(Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec!["x".to_string(), "y".to_string()],
+        }])),
+        conf.get_string_list(&ids[2])
+    );
+    assert_eq!(
+        Ok(Some(vec![DictEdit {
+            action: DictEditAction::Replace,
+            items: hashmap! {"LANG".to_string() => Val::String("C".to_string())},
+        }])),
+        conf.get_dict(&ids[3])
+    );
+    assert_eq!(Ok(Some("done".to_string())), conf.get_string(&ids[4]));
+    assert_eq!(Ok(None), conf.get_string(&ids[5]));
+}
+
+#[test]
+fn test_interpolation_resolved_at_parse_time() {
+    let token_heavy_value = vec!["%(a)s/%(b)s"; 50].join(":");
+    let conf = {
+        let seed_values = HashMap::from([
+            ("a".to_string(), "%(b)s%(b)s".to_string()),
+            ("b".to_string(), "x".to_string()),
+        ]);
+        ConfigReader::new(
+            Config::parse_reader(
+                Cursor::new(format!("[scope]\npath = '{token_heavy_value}'\n")),
+                "pants.toml",
+                &seed_values,
+            )
+            .unwrap(),
+            FromfileExpander::relative_to_cwd(),
+        )
+    };
+
+    // The seed values are gone, but every read returns the string that was interpolated when the
+    // config was parsed, so there is nothing to cache or re-substitute.
+    let expected = vec!["xx/x"; 50].join(":");
+    for _ in 0..3 {
+        assert_eq!(
+            Ok(Some(expected.clone())),
+            conf.get_string(&option_id!(["scope"], "path"))
+        );
+    }
+}
+
+#[test]
+fn test_builtin_placeholders() {
+    let seed_values = HashMap::from([
+        ("user".to_string(), "alice".to_string()),
+        ("home".to_string(), "/home/alice".to_string()),
+    ]);
+    let conf = ConfigReader::new(
+        Config::parse_reader(
+            Cursor::new(
+                "[DEFAULT]\n\
+                 cache_root = '%(home)s/.cache'\n\
+                 [a]\n\
+                 cache = '%(cache_root)s/%(user)s'\n",
+            ),
+            "pants.toml",
+            &seed_values,
+        )
+        .unwrap(),
+        FromfileExpander::relative_to_cwd(),
+    );
+    assert_eq!(
+        Some("/home/alice/.cache/alice".to_string()),
+        conf.get_string(&option_id!(["a"], "cache")).unwrap()
+    );
+
+    // Without seeds, the tokens resolve to the current user and their home directory.
+    let conf = config("[a]\nowner = '%(user)s'\ndir = '%(home)s/src'\n");
+    assert_eq!(
+        Some(whoami::username()),
+        conf.get_string(&option_id!(["a"], "owner")).unwrap()
+    );
+    assert_eq!(
+        Some(format!("{}/src", shellexpand::tilde("~"))),
+        conf.get_string(&option_id!(["a"], "dir")).unwrap()
+    );
+
+    // A section's own value of the same name overrides the token, so can't refer to it.
+    assert_eq!(
+        "Placeholders refer to each other in a cycle: home -> home in config file pants.toml, \
+         section a, key home",
+        Config::parse_reader(
+            Cursor::new("[a]\nhome = '%(home)s'\n"),
+            "pants.toml",
+            &HashMap::new()
+        )
+        .err()
+        .unwrap()
+    );
+
+    // Other unknown tokens are still errors.
+    assert!(maybe_config("[a]\nowner = '%(username)s'\n")
+        .err()
+        .unwrap()
+        .starts_with("Unknown value for placeholder `username`"));
+}
+
+#[test]
+fn test_check_relation() {
+    let conf = config("[a]\nthree = 3\nother_three = 3\nfive = 5\n");
+    let three = option_id!(["a"], "three");
+    let other_three = option_id!(["a"], "other_three");
+    let five = option_id!(["a"], "five");
+
+    let check = |a: &OptionId, b: &OptionId, rel: Relation| conf.check_relation(a, b, rel);
+    assert_eq!(Ok(()), check(&three, &five, Relation::Lt));
+    assert_eq!(Ok(()), check(&three, &other_three, Relation::Le));
+    assert_eq!(Ok(()), check(&three, &other_three, Relation::Eq));
+    assert_eq!(Ok(()), check(&three, &five, Relation::Ne));
+    assert_eq!(Ok(()), check(&five, &three, Relation::Ge));
+    assert_eq!(Ok(()), check(&five, &three, Relation::Gt));
+
+    let violation = |a: &str, rel: &str, b: &str, x: i64, y: i64| {
+        Err(format!(
+            "Expected [a] {a} {rel} [a] {b} but [a] {a}={x} and [a] {b}={y}"
+        ))
+    };
+    assert_eq!(
+        violation("three", "<", "other_three", 3, 3),
+        check(&three, &other_three, Relation::Lt)
+    );
+    assert_eq!(
+        violation("five", "<=", "three", 5, 3),
+        check(&five, &three, Relation::Le)
+    );
+    assert_eq!(
+        violation("three", "==", "five", 3, 5),
+        check(&three, &five, Relation::Eq)
+    );
+    assert_eq!(
+        violation("three", "!=", "other_three", 3, 3),
+        check(&three, &other_three, Relation::Ne)
+    );
+    assert_eq!(
+        violation("three", ">=", "five", 3, 5),
+        check(&three, &five, Relation::Ge)
+    );
+    assert_eq!(
+        violation("three", ">", "other_three", 3, 3),
+        check(&three, &other_three, Relation::Gt)
+    );
+
+    // Nothing is checked unless both options are set.
+    let unset = option_id!(["a"], "unset");
+    assert_eq!(Ok(()), check(&five, &unset, Relation::Lt));
+    assert_eq!(Ok(()), check(&unset, &three, Relation::Gt));
+}
+
+#[test]
+fn test_file_references() {
+    let dir = TempDir::new().unwrap();
+    let write = |name: &str, content: &str| {
+        let path = dir.path().join(name);
+        File::create(&path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+        path
+    };
+    let shared_path = write(
+        "shared.toml",
+        "[python]\n\
+         version = '3.11'\n\
+         jobs = 4\n",
+    );
+    let cycle_a_path = write("cycle_a.toml", "[cycle]\na = '${cycle_b.toml:cycle.b}'\n");
+    write("cycle_b.toml", "[cycle]\nb = '${cycle_a.toml:cycle.a}'\n");
+    let parse = |content: &str| {
+        let path = write("pants.toml", content);
+        Config::parse(&ConfigSource::from_file(path).unwrap(), &HashMap::new())
+            .map(|config| ConfigReader::new(config, FromfileExpander::relative_to_cwd()))
+    };
+
+    let conf = parse(
+        "[python]\n\
+         interpreter = 'python${shared.toml:python.version}'\n\
+         jobs = '${shared.toml:python.jobs}'\n\
+         versions = ['${shared.toml:python.version}', '3.12']\n",
+    )
+    .unwrap();
+    assert_eq!(
+        Some("python3.11".to_string()),
+        conf.get_string(&option_id!(["python"], "interpreter"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some(4),
+        conf.get_int(&option_id!(["python"], "jobs")).unwrap()
+    );
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec!["3.11".to_string(), "3.12".to_string()],
+        }]),
+        conf.get_string_list(&option_id!(["python"], "versions"))
+            .unwrap()
+    );
+
+    let shared = fs::canonicalize(shared_path).unwrap();
+    let cycle_a = fs::canonicalize(cycle_a_path).unwrap();
+    let cycle_b = fs::canonicalize(dir.path().join("cycle_b.toml")).unwrap();
+    let main_path = dir.path().join("pants.toml");
+    let err = |content: &str| parse(content).err().unwrap();
+    assert_eq!(
+        format!(
+            "File {} referenced by ${{missing.toml:python.version}} does not exist in config \
+             file {}, section a, key b",
+            dir.path().join("missing.toml").display(),
+            main_path.display()
+        ),
+        err("[a]\nb = '${missing.toml:python.version}'\n")
+    );
+    assert_eq!(
+        format!(
+            "File {} referenced by ${{shared.toml:go.version}} has no [go] section in config \
+             file {}, section a, key b",
+            shared.display(),
+            main_path.display()
+        ),
+        err("[a]\nb = '${shared.toml:go.version}'\n")
+    );
+    assert_eq!(
+        format!(
+            "Section [python] of file {} referenced by ${{shared.toml:python.pip}} has no option \
+             pip in config file {}, section a, key b",
+            shared.display(),
+            main_path.display()
+        ),
+        err("[a]\nb = '${shared.toml:python.pip}'\n")
+    );
+    assert!(
+        err("[a]\nb = '${cycle_a.toml:cycle.a}'\n").starts_with(&format!(
+            "Cycle in references between config files: {} -> {} -> {}",
+            cycle_a.display(),
+            cycle_b.display(),
+            cycle_a.display()
+        ))
+    );
+}
+
+#[test]
+fn test_platform_values() {
+    let content = "[GLOBAL]\n\
+                   cache_dir = { linux = '/var/cache', macos = '/Caches', default = '/tmp' }\n\
+                   jobs = { linux = 8, windows = 2 }\n\
+                   bad = { linux = 'a', freebsd = 'b' }\n\
+                   not_platforms = { a = 1 }\n\
+                   empty = {}\n";
+    let read = |platform: &str| {
+        let conf = config(content).with_platform(platform);
+        (
+            conf.get_string(&option_id!("cache", "dir")).unwrap(),
+            conf.get_int(&option_id!("jobs")).unwrap(),
+        )
+    };
+
+    assert_eq!((Some("/var/cache".to_string()), Some(8)), read("linux"));
+    assert_eq!((Some("/Caches".to_string()), None), read("macos"));
+    assert_eq!((Some("/tmp".to_string()), Some(2)), read("windows"));
+    assert_eq!(
+        Err(
+            "Unknown platform `freebsd` in the value of [GLOBAL] bad: expected linux, macos, \
+            windows or default"
+                .to_string()
+        ),
+        config(content).get_string(&option_id!("bad"))
+    );
+    // Tables without any platform keys are just values of the wrong type.
+    for option_name in ["not_platforms", "empty"] {
+        let id = OptionId::new(Scope::Global, [option_name].into_iter(), None).unwrap();
+        let err = config(content).get_string(&id).unwrap_err();
+        assert!(
+            err.starts_with(&format!(
+                "Expected [GLOBAL] {option_name} to be a string but given"
+            )),
+            "Unexpected error: {err}"
+        );
+    }
+}
+
+#[test]
+fn test_merge_filtered() {
+    let base = Config::parse(
+        &ConfigSource {
+            path: PathBuf::from("pants.toml"),
+            content: "[GLOBAL]\nlevel = 'info'\n[python]\nresolver = 'pip'\n".to_string(),
+        },
+        &HashMap::new(),
+    )
+    .unwrap();
+    let remote = Config::parse(
+        &ConfigSource {
+            path: PathBuf::from("remote.toml"),
+            content: "[GLOBAL]\nlevel = 'debug'\nremote_cache_read = true\n\
+                      [python]\nresolver = 'uv'\n\
+                      [mypy]\n_inherits = 'python'\n\
+                      [_locked]\noptions = ['GLOBAL.level']\n"
+                .to_string(),
+        },
+        &HashMap::new(),
+    )
+    .unwrap();
+    let allowed = HashSet::from([
+        "GLOBAL.remote_cache_read".to_string(),
+        "python.resolver".to_string(),
+    ]);
+
+    let merged = base.merge_filtered(&remote, &allowed);
+    assert_eq!(vec!["GLOBAL", "python"], merged.sections());
+    let merged = ConfigReader::new(merged, FromfileExpander::relative_to_cwd());
+    assert_eq!(
+        Ok(Some("info".to_string())),
+        merged.get_string(&option_id!("level"))
+    );
+    assert_eq!(
+        Ok(Some(true)),
+        merged.get_bool(&option_id!("remote", "cache", "read"))
+    );
+    assert_eq!(
+        Ok(Some("uv".to_string())),
+        merged.get_string(&option_id!(["python"], "resolver"))
+    );
+    // The remote's scope inheritance is ignored along with its options.
+    assert_eq!(
+        Ok(None),
+        merged.get_string(&option_id!(["mypy"], "resolver"))
+    );
+}
+
+#[test]
+fn test_negated_bools() {
+    let conf = config(
+        "[DEFAULT]\n\
+         no_verbose = true\n\
+         quiet = true\n\
+         local = true\n\
+         no_local = true\n\
+         [scope]\n\
+         colors = true\n\
+         no_watch = true\n\
+         no_dynamic_ui = false\n\
+         pantsd = true\n\
+         no_pantsd = false\n\
+         verbose = true\n\
+         no_quiet = true\n",
+    );
+
+    assert_eq!(
+        Ok(Some(true)),
+        conf.get_bool(&option_id!(["scope"], "colors"))
+    );
+    assert_eq!(
+        Ok(Some(false)),
+        conf.get_bool(&option_id!(["scope"], "watch"))
+    );
+    assert_eq!(
+        Ok(Some(true)),
+        conf.get_bool(&option_id!(["scope"], "dynamic", "ui"))
+    );
+    assert_eq!(Ok(None), conf.get_bool(&option_id!(["scope"], "missing")));
+    assert_eq!(
+        Err("Both pantsd and no_pantsd are set; they conflict".to_owned()),
+        conf.get_bool(&option_id!(["scope"], "pantsd"))
+    );
+    // Either spelling in the scope's section overrides the other spelling in DEFAULT.
+    assert_eq!(
+        Ok(Some(true)),
+        conf.get_bool(&option_id!(["scope"], "verbose"))
+    );
+    assert_eq!(
+        Ok(Some(false)),
+        conf.get_bool(&option_id!(["scope"], "quiet"))
+    );
+    assert_eq!(
+        Ok(Some(false)),
+        conf.get_bool(&option_id!(["other"], "verbose"))
+    );
+    assert_eq!(
+        Err("Both local and no_local are set; they conflict".to_owned()),
+        conf.get_bool(&option_id!(["scope"], "local"))
+    );
+}
+
+#[test]
+fn test_parse_with_env_allowlist() {
+    let parse = |content: &str| {
+        Config::parse_with_env_allowlist(
+            &ConfigSource {
+                path: PathBuf::from("pants.toml"),
+                content: content.to_string(),
+            },
+            &HashMap::from([
+                ("env.HOME".to_string(), "/home/me".to_string()),
+                ("env.SECRET".to_string(), "hunter2".to_string()),
+            ]),
+            &HashSet::from(["HOME".to_string()]),
+        )
+        .map(|config| {
+            ConfigReader::new(config, FromfileExpander::relative_to_cwd())
+                .get_string(&option_id!("cache"))
+                .unwrap()
+        })
+    };
+
+    assert_eq!(
+        Ok(Some("/home/me/.cache".to_string())),
+        parse("[GLOBAL]\ncache = '%(env.HOME)s/.cache'\n")
+    );
+    assert_eq!(
+        Err(
+            "Interpolation of env var SECRET is not permitted in config file pants.toml, \
+            section GLOBAL, key cache"
+                .to_string()
+        ),
+        parse("[GLOBAL]\ncache = '%(env.SECRET)s'\n")
+    );
+    // An env var that isn't on the allowlist isn't permitted even when it isn't set.
+    assert_eq!(
+        Err(
+            "Interpolation of env var UNSET is not permitted in config file pants.toml, \
+            section DEFAULT, key dir"
+                .to_string()
+        ),
+        parse("[DEFAULT]\ndir = '%(env.UNSET)s'\n")
+    );
+}
+
+#[test]
+fn test_template() {
+    let defaults = HashMap::from([
+        (option_id!("level"), Value::String("info".to_owned())),
+        (option_id!("pantsd"), Value::Boolean(true)),
+        (
+            option_id!(["python"], "interpreter_constraints"),
+            Value::Array(vec![Value::String(">=3.8,<3.12".to_owned())]),
+        ),
+        (
+            option_id!(["subprocess-environment"], "env_vars"),
+            Value::Table(Table::from_iter([(
+                "A.B".to_owned(),
+                Value::String("x".to_owned()),
+            )])),
+        ),
+    ]);
+    let help = HashMap::from([
+        (
+            option_id!("level"),
+            "The log level.\n\nOne of debug, info or warn.".to_owned(),
+        ),
+        (
+            option_id!(["python"], "interpreter_constraints"),
+            "The Python interpreters to use.".to_owned(),
+        ),
+    ]);
+
+    let template = Config::template(&defaults, &help);
+    assert_eq!(
+        "[GLOBAL]\n\
+         \n\
+         # The log level.\n\
+         #\n\
+         # One of debug, info or warn.\n\
+         #level = \"info\"\n\
+         \n\
+         #pantsd = true\n\
+         \n\
+         [python]\n\
+         \n\
+         # The Python interpreters to use.\n\
+         #interpreter_constraints = [\">=3.8,<3.12\"]\n\
+         \n\
+         [subprocess-environment]\n\
+         \n\
+         #env_vars = { \"A.B\" = \"x\" }\n",
+        template
+    );
+
+    // A template sets no options, and sets each option to its default once it is uncommented.
+    let option_count = |config: &Config| {
+        config
+            .sections()
+            .into_iter()
+            .map(|section_name| config.option_names(section_name).len())
+            .sum::<usize>()
+    };
+    assert_eq!(0, option_count(&parsed_config(&template)));
+    let uncommented = template
+        .lines()
+        .map(|line| match line.strip_prefix('#') {
+            Some(option) if !option.starts_with(' ') && !option.is_empty() => option,
+            _ => line,
+        })
+        .join("\n");
+    let uncommented = parsed_config(&uncommented);
+    assert_eq!(defaults.len(), option_count(&uncommented));
+    assert!(uncommented.non_default_options(&defaults).is_empty());
+}
+
+#[test]
+fn test_get_int_ranges() {
+    let conf = config(
+        "[GLOBAL]\n\
+         cpus = '1-3, 5,7-9'\n\
+         port = '8080'\n\
+         offsets = '-3--1'\n\
+         reversed = '1-3,9-7'\n\
+         malformed = '1-3,x'\n\
+         empty_token = '1,,2'\n",
+    );
+    let get = |name: &str| {
+        conf.get_int_ranges(&OptionId::new(Scope::Global, [name].into_iter(), None).unwrap())
+    };
+
+    assert_eq!(Ok(Some(vec![1..=3, 5..=5, 7..=9])), get("cpus"));
+    assert_eq!(Ok(Some(vec![8080..=8080])), get("port"));
+    assert_eq!(Ok(Some(vec![-3..=-1])), get("offsets"));
+    assert_eq!(Ok(None), get("missing"));
+    assert_eq!(
+        Err(
+            "Invalid range '9-7' in '1-3,9-7': start is greater than end for [GLOBAL] reversed"
+                .to_string()
+        ),
+        get("reversed")
+    );
+    assert_eq!(
+        Err("Invalid range 'x' in '1-3,x' for [GLOBAL] malformed".to_string()),
+        get("malformed")
+    );
+    assert_eq!(
+        Err("Invalid range '' in '1,,2' for [GLOBAL] empty_token".to_string()),
+        get("empty_token")
+    );
+}
+
+#[test]
+fn test_lint() {
+    let messages = |content: &str| {
+        parsed_config(content)
+            .lint()
+            .into_iter()
+            .map(|lint| lint.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert!(messages(
+        "[python]\n\
+         interpreter_constraints = ['>=3.8']\n\
+         resolves = { a = 'a.lock', b = 'b.lock' }\n\
+         extra = { add = ['x'], remove = ['y'] }\n"
+    )
+    .is_empty());
+
+    let long_array = format!(
+        "[GLOBAL]\nbackend_packages = [{}]\n",
+        (0..12)
+            .map(|i| format!("'item{i}'"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    assert_eq!(
+        vec![
+            "pants.toml:2: [GLOBAL] backend_packages: Inline array is on a line of 129 characters: \
+            consider writing one item per line"
+                .to_string()
+        ],
+        messages(&long_array)
+    );
+    // The same items, one per line, are fine.
+    assert!(messages(&long_array.replace(", ", ",\n")).is_empty());
+
+    assert_eq!(
+        vec![
+            "pants.toml:3: [subprocess] env: Inline tables are nested 3 deep: consider using a \
+            separate section"
+                .to_string()
+        ],
+        messages(
+            "[subprocess]\n\n\
+                  env = { a = { b = { c = 1 } } }\n"
+        )
+    );
+
+    assert_eq!(
+        vec![
+            "pants.toml:2: [python] tags: \"a\" is listed more than once".to_string(),
+            "pants.toml:3: [python] extra: \"x\" is added more than once".to_string(),
+            "pants.toml:3: [python] extra: \"x\" is both added and removed".to_string(),
+        ],
+        messages(
+            "[python]\n\
+             tags = ['a', 'b', 'a']\n\
+             extra.add = ['x', 'x']\n\
+             extra.remove = ['x']\n"
+        )
+    );
+
+    assert_eq!(
+        vec![
+            "pants.toml:5: [python] indexes: Item of multi-line array is indented differently \
+            from the item before it"
+                .to_string()
+        ],
+        messages(
+            "[python]\n\
+             indexes = [\n\
+             \x20   'https://a.example.com[1]', # Comments [ are ignored.\n\
+             \x20   'https://b.example.com',\n\
+             \t'https://c.example.com',\n\
+             ]\n\
+             extra = [\n\
+             \x20 ['x',\n\
+             \x20    'y'],\n\
+             \x20 ['z'],\n\
+             ]\n"
+        )
+    );
+    // TOML doesn't join array items that are missing a comma between them, but fails to parse.
+    assert!(Config::parse(
+        &ConfigSource {
+            path: PathBuf::from("pants.toml"),
+            content: "[python]\nindexes = [\n    'a'\n    'b',\n]\n".to_string(),
+        },
+        &HashMap::new(),
+    )
+    .is_err());
+
+    // Options that weren't parsed from a file have no line.
+    let table = toml::from_str("[python]\ntags = ['a', 'a']\n").unwrap();
+    let lints = Config::from_table(table, "overrides").lint();
+    assert_eq!(1, lints.len());
+    assert_eq!(None, lints[0].line);
+    assert_eq!(
+        "overrides: [python] tags: \"a\" is listed more than once",
+        lints[0].to_string()
+    );
+}
+
+#[test]
+fn test_validate_ascii_names() {
+    assert_eq!(
+        Ok(()),
+        parsed_config("[python]\nresolver = 'pip'\nnote = 'Ünïcödé values are fine'\n")
+            .validate_ascii_names()
+    );
+    // The `о` of `pythоn` and the `е` of `rеsolver` are Cyrillic. TOML only allows non-ASCII
+    // names when quoted.
+    assert_eq!(
+        Err(vec![
+            "Option name café in [python] contains the non-ASCII character 'é' (U+00E9)"
+                .to_string(),
+            "Section name [pythоn] contains the non-ASCII character 'о' (U+043E)".to_string(),
+            "Option name rеsolver in [pythоn] contains the non-ASCII character 'е' (U+0435)"
+                .to_string(),
+        ]),
+        parsed_config(
+            "[python]\nresolver = 'pip'\n\"café\" = true\n[\"pythоn\"]\n\"rеsolver\" = 'uv'\n"
+        )
+        .validate_ascii_names()
+    );
+}
+
+#[test]
+fn test_validate_placeholders() {
+    let placeholders = [
+        Regex::new("^CHANGEME$").unwrap(),
+        Regex::new("^<.*>$").unwrap(),
+    ];
+    let validate = |content: &str| parsed_config(content).validate_placeholders(&placeholders);
+
+    assert_eq!(
+        Ok(()),
+        validate(
+            "[python]\n\
+             pip_version = '23.1'\n\
+             note = 'Do not CHANGEME'\n\
+             jobs = 4\n"
+        )
+    );
+    assert_eq!(
+        Err("Option [auth] token still has placeholder value 'CHANGEME'".to_string()),
+        validate("[auth]\nuser = 'me'\ntoken = 'CHANGEME'\n")
+    );
+    assert_eq!(
+        Err("Option [python] indexes still has placeholder value '<set me>'".to_string()),
+        validate("[python]\nindexes = ['https://pypi.org/simple', '<set me>']\n")
+    );
+    assert_eq!(
+        Err("Option [subprocess] env_vars still has placeholder value '<token>'".to_string()),
+        validate("[subprocess]\nenv_vars = { TOKEN = '<token>' }\n")
+    );
+    // Without any placeholder patterns, nothing is a placeholder.
+    assert_eq!(
+        Ok(()),
+        parsed_config("[auth]\ntoken = 'CHANGEME'\n").validate_placeholders(&[])
+    );
+}
+
+#[test]
+fn test_inherits() {
+    let conf = config(
+        "[python]\n\
+         version = '3.11'\n\
+         resolver = 'pip'\n\
+         indexes = ['a']\n\
+         env = { A = '1' }\n\
+         [mypy]\n\
+         _inherits = 'python'\n\
+         version = '3.12'\n\
+         indexes.add = ['b']\n\
+         env.add = { B = '2' }\n\
+         [mypy-strict]\n\
+         _inherits = 'mypy'\n",
+    );
+
+    assert_eq!(
+        Ok(Some("3.12".to_string())),
+        conf.get_string(&option_id!(["mypy"], "version"))
+    );
+    assert_eq!(
+        Ok(Some("pip".to_string())),
+        conf.get_string(&option_id!(["mypy"], "resolver"))
+    );
+    assert_eq!(
+        Ok(Some("3.12".to_string())),
+        conf.get_string(&option_id!(["mypy-strict"], "version"))
+    );
+    assert_eq!(
+        Ok(Some("3.11".to_string())),
+        conf.get_string(&option_id!(["python"], "version"))
+    );
+    // `_inherits` is not itself an option.
+    assert_eq!(
+        Ok(None),
+        conf.get_string(&option_id!(["mypy"], "_inherits"))
+    );
+
+    // Edits in an inheriting scope apply on top of those of its parent.
+    assert_eq!(
+        Ok(Some(vec![
+            ListEdit {
+                action: ListEditAction::Replace,
+                items: vec!["a".to_string()],
+            },
+            ListEdit {
+                action: ListEditAction::Add,
+                items: vec!["b".to_string()],
+            },
+        ])),
+        conf.get_string_list(&option_id!(["mypy-strict"], "indexes"))
+    );
+    assert_eq!(
+        Ok(Some(vec![
+            DictEdit {
+                action: DictEditAction::Replace,
+                items: hashmap! {"A".to_string() => Val::String("1".to_string())},
+            },
+            DictEdit {
+                action: DictEditAction::Add,
+                items: hashmap! {"B".to_string() => Val::String("2".to_string())},
+            },
+        ])),
+        conf.get_dict(&option_id!(["mypy"], "env"))
+    );
+
+    let err = maybe_config("[a]\n_inherits = 'b'\n[b]\n_inherits = 'c'\n[c]\n_inherits = 'a'\n")
+        .err()
+        .unwrap();
+    assert!(err.starts_with("Scopes inherit from each other in a cycle: a -> b -> c -> a"));
+
+    let err = maybe_config("[a]\n_inherits = 1\n").err().unwrap();
+    assert!(
+        err.starts_with("Expected _inherits in section [a] to be the name of a scope but given 1")
+    );
+
+    // A cycle created by merging configs is warned about, and reading stops at the repeated scope.
+    let merged = parsed_config("[a]\n_inherits = 'b'\nx = 1\n")
+        .merge(&parsed_config("[b]\n_inherits = 'a'\ny = 2\n"));
+    assert_eq!(
+        &["Scopes inherit from each other in a cycle: a -> b -> a".to_string()],
+        merged.warnings()
+    );
+    let merged = ConfigReader::new(merged, FromfileExpander::relative_to_cwd());
+    assert_eq!(Ok(Some(2)), merged.get_int(&option_id!(["a"], "y")));
+    assert_eq!(Ok(Some(1)), merged.get_int(&option_id!(["b"], "x")));
+    assert_eq!(Ok(None), merged.get_int(&option_id!(["a"], "z")));
+}
+
+#[test]
+fn test_explain_unset() {
+    let conf = parsed_config(
+        "[DEFAULT]\n\
+         level = 'warn'\n\
+         [python]\n\
+         pip_version = '23.1'\n\
+         extra_requirements = { clear = true }\n\
+         constraints = { clear = true, add = ['>=3.9'] }\n",
+    );
+    assert_eq!(
+        None,
+        conf.explain_unset(&option_id!(["python"], "pip", "version"))
+    );
+    assert_eq!(None, conf.explain_unset(&option_id!(["pytest"], "level")));
+    assert_eq!(
+        None,
+        conf.explain_unset(&option_id!(["python"], "constraints"))
+    );
+    assert_eq!(
+        Some(UnsetReason::ScopeMissing),
+        conf.explain_unset(&option_id!(["pytest"], "args"))
+    );
+    assert_eq!(
+        Some(UnsetReason::OptionMissing),
+        conf.explain_unset(&option_id!(["python"], "resolver"))
+    );
+    assert_eq!(
+        Some(UnsetReason::ExplicitlyCleared),
+        conf.explain_unset(&option_id!(["python"], "extra", "requirements"))
+    );
+}
+
+#[test]
+fn test_require_sections() {
+    let conf = parsed_config("[GLOBAL]\na = 1\n[python]\na = 1\n");
+    assert_eq!(Ok(()), conf.require_sections(&["GLOBAL", "python"]));
+    assert_eq!(Ok(()), conf.require_sections(&[]));
+    assert_eq!(
+        Err(vec!["pytest".to_string()]),
+        conf.require_sections(&["GLOBAL", "pytest", "python"])
+    );
+    assert_eq!(
+        Err(vec!["pytest".to_string(), "go".to_string()]),
+        conf.require_sections(&["pytest", "go"])
+    );
+    assert_eq!(
+        Err(vec!["GLOBAL".to_string()]),
+        parsed_config("").require_sections(&["GLOBAL"])
+    );
+}
+
+#[test]
+fn test_as_option_map() {
+    let conf = parsed_config(
+        "[DEFAULT]\n\
+         level = 'warn'\n\
+         [python]\n\
+         pip_version = '23.1'\n\
+         interpreter_constraints = ['>=3.9']\n\
+         [GLOBAL]\n\
+         pantsd = false\n\
+         level = 'info'\n\
+         [pytest]\n\
+         timeout_default = 60\n\
+         env_vars = { A = 'b' }\n",
+    );
+    let option_map = conf.as_option_map();
+    assert_eq!(
+        vec![
+            (option_id!("level"), Value::from("info")),
+            (option_id!("pantsd"), Value::from(false)),
+            (
+                option_id!(["pytest"], "timeout", "default"),
+                Value::from(60)
+            ),
+            (
+                option_id!(["python"], "pip", "version"),
+                Value::from("23.1")
+            ),
+        ],
+        option_map.into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_scopes_matching() {
+    let conf = parsed_config(
+        "[DEFAULT]\na = 1\n[go]\na = 1\n[python]\na = 1\n['python.lint']\na = 1\n\
+         ['python.test']\na = 1\n[pythonic]\na = 1\n",
+    );
+    assert_eq!(
+        vec!["python.lint", "python.test"],
+        conf.scopes_matching("python.*")
+    );
+    assert_eq!(
+        vec!["python", "python.lint", "python.test", "pythonic"],
+        conf.scopes_matching("python*")
+    );
+    assert_eq!(vec!["python.test"], conf.scopes_matching("*.test"));
+    assert_eq!(vec!["go"], conf.scopes_matching("go"));
+    assert_eq!(
+        vec!["go", "python", "python.lint", "python.test", "pythonic"],
+        conf.scopes_matching("*")
+    );
+    assert!(conf.scopes_matching("rust.*").is_empty());
+}
+
+#[test]
+fn test_config_version() {
+    let conf = parsed_config("config_version = 1\n[python]\nversion = '3.11'\n");
+    assert_eq!(vec!["python"], conf.sections());
+    assert!(conf.warnings().is_empty());
+
+    assert!(parsed_config("[python]\nversion = '3.11'\n")
+        .warnings()
+        .is_empty());
+
+    assert_eq!(
+        vec![
+            "Config file pants.toml is for config version 0, which is older than the supported \
+             version 1: some options may have changed meaning"
+        ],
+        parsed_config("config_version = 0\n").warnings()
+    );
+
+    let parse = |content: &str| {
+        Config::parse_reader(Cursor::new(content), "pants.toml", &HashMap::new())
+            .err()
+            .unwrap()
+    };
+    assert_eq!(
+        "Config version 2 is newer than supported version 1; upgrade Pants",
+        parse("config_version = 2\n[python]\nversion = '3.11'\n")
+    );
+    assert_eq!(
+        "Expected config_version in config file pants.toml to be an int but given \"1\"",
+        parse("config_version = '1'\n")
+    );
+}
+
+#[test]
+fn test_diff() {
+    let old = parsed_config(
+        "[python]\n\
+     foo = 'a'\n\
+     bar = 1\n\
+     same = true\n",
+    );
+    let new = parsed_config(
+        "[python]\n\
+     foo = 'b'\n\
+     same = true\n\
+     [go]\n\
+     baz = [1, 2]\n",
+    );
+    assert_eq!(
+        vec![
+            ConfigChange::Added {
+                section: "go".to_string(),
+                option: "baz".to_string(),
+                value: toml::Value::Array(vec![1.into(), 2.into()]),
+            },
+            ConfigChange::Removed {
+                section: "python".to_string(),
+                option: "bar".to_string(),
+                value: 1.into(),
+            },
+            ConfigChange::Changed {
+                section: "python".to_string(),
+                option: "foo".to_string(),
+                old: "a".into(),
+                new: "b".into(),
+            },
+        ],
+        old.diff(&new)
+    );
+    assert_eq!(
+        "+ [go] baz = [1, 2]\n\
+         - [python] bar = 1\n\
+         - [python] foo = \"a\"\n\
+         + [python] foo = \"b\"\n",
+        old.diff_text(&new)
+    );
+    assert_eq!("", old.diff_text(&old));
+}
+
+#[test]
+fn test_scope_aliases() {
+    let conf = ConfigReader::new(
+        parsed_config(
+            "[pytest]\n\
+         args = ['-x']\n\
+         timeout = 10\n\
+         [python-test]\n\
+         args.add = ['-v']\n\
+         timeout = 20\n",
+        )
+        .with_scope_aliases(HashMap::from([(
+            "pytest".to_string(),
+            "python-test".to_string(),
+        )])),
+        FromfileExpander::relative_to_cwd(),
+    );
+
+    // Values in the new scope's section win, and list edits apply on top of the old section's.
+    for scope_name in ["python-test", "pytest"] {
+        let timeout_id = OptionId::new(Scope::named(scope_name), ["timeout"].iter(), None).unwrap();
+        assert_eq!(Some(20), conf.get_int(&timeout_id).unwrap());
+        let args_id = OptionId::new(Scope::named(scope_name), ["args"].iter(), None).unwrap();
+        assert_eq!(
+            Some(vec![
+                ListEdit {
+                    action: ListEditAction::Replace,
+                    items: vec!["-x".to_string()],
+                },
+                ListEdit {
+                    action: ListEditAction::Add,
+                    items: vec!["-v".to_string()],
+                },
+            ]),
+            conf.get_string_list(&args_id).unwrap()
+        );
+    }
+
+    let aliased_only = parsed_config("[pytest]\ntimeout = 10\n").with_scope_aliases(HashMap::from(
+        [("pytest".to_string(), "python-test".to_string())],
+    ));
+    assert_eq!(
+        vec!["Config section [pytest] is deprecated: use [python-test] instead".to_string()],
+        aliased_only.warnings()
+    );
+    let conf = ConfigReader::new(aliased_only, FromfileExpander::relative_to_cwd());
+    assert_eq!(
+        Some(10),
+        conf.get_int(&option_id!(["python-test"], "timeout"))
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_empty_section_warnings() {
+    let conf = parsed_config("[python]\n[pytest]\nargs = ['-x']\n").with_empty_section_warnings();
+    assert_eq!(
+        vec!["Config section [python] contains no options".to_string()],
+        conf.warnings()
+    );
+
+    // Warnings are opt-in.
+    assert!(parsed_config("[python]\n").warnings().is_empty());
+}
+
+#[test]
+fn test_option_metadata() {
+    let conf = parsed_config(
+        "[python]\n\
+         # @pants: deprecated since 2.20\n\
+         # Set this explicitly for now.\n\
+         # @pants: owner build-infra\n\
+         # @pants: reviewer somebody\n\
+         resolves_generate_lockfiles = false\n\
+         # @pants: ticket INFRA-123\n\
+         interpreter_constraints.add = ['>=3.9']\n\
+         \n\
+         # @pants: owner nobody\n\
+         \n\
+         pip_version = '23.1'\n\
+         [GLOBAL]\n\
+         # @pants: ticket INFRA-456\n\
+         \"level\" = 'info'\n",
+    );
+
+    assert_eq!(
+        Some(OptionMetadata {
+            deprecated: Some("since 2.20".to_string()),
+            owner: Some("build-infra".to_string()),
+            ticket: None,
+        }),
+        conf.option_metadata(&option_id!(["python"], "resolves", "generate", "lockfiles"))
+    );
+    assert_eq!(
+        Some(OptionMetadata {
+            ticket: Some("INFRA-123".to_string()),
+            ..OptionMetadata::default()
+        }),
+        conf.option_metadata(&option_id!(["python"], "interpreter", "constraints"))
+    );
+    assert_eq!(
+        Some(OptionMetadata {
+            ticket: Some("INFRA-456".to_string()),
+            ..OptionMetadata::default()
+        }),
+        conf.option_metadata(&option_id!("level"))
+    );
+    // Annotations separated from an option by a blank line are discarded.
+    assert_eq!(
+        None,
+        conf.option_metadata(&option_id!(["python"], "pip", "version"))
+    );
+}
+
+#[test]
+fn test_get_string_list_expanded() {
+    let conf = config(
+        "[python]\n\
+         interpreter_versions = ['py3{9,10,11}', 'pypy']\n\
+         platforms.add = ['{linux,macos}_{x86_64,arm{64,v7}}']\n\
+         literals = ['\\{a\\,b\\}', 'x{a}y', 'c{\\,,d}']\n\
+         unbalanced = ['py3{9,10']\n",
+    );
+    let get = |option_name: &str| {
+        conf.get_string_list_expanded(
+            &OptionId::new(Scope::named("python"), [option_name].iter(), None).unwrap(),
+        )
+    };
+
+    let replace = |items: Vec<&str>| ListEdit {
+        action: ListEditAction::Replace,
+        items: items.into_iter().map(str::to_string).collect(),
+    };
+    assert_eq!(
+        Some(vec![replace(vec!["py39", "py310", "py311", "pypy"])]),
+        get("interpreter_versions").unwrap()
+    );
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Add,
+            items: [
+                "linux_x86_64",
+                "linux_arm64",
+                "linux_armv7",
+                "macos_x86_64",
+                "macos_arm64",
+                "macos_armv7"
+            ]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        }]),
+        get("platforms").unwrap()
+    );
+    assert_eq!(
+        Some(vec![replace(vec!["{a,b}", "x{a}y", "c,", "cd"])]),
+        get("literals").unwrap()
+    );
+    assert_eq!(
+        "Failed to expand [python] unbalanced: Unmatched '{' in brace pattern py3{9,10",
+        get("unbalanced").unwrap_err()
+    );
+}
+
+#[test]
+fn test_minimal_for() {
+    let conf = parsed_config(
+        "[DEFAULT]\n\
+         level = 'warn'\n\
+         [GLOBAL]\n\
+         pants_version = '2.20.0'\n\
+         [python]\n\
+         interpreter_constraints = ['>=3.9']\n\
+         pip_version = '23.1'\n\
+         [pytest]\n\
+         args = ['-x']\n",
+    );
+    let minimal = conf.minimal_for(&[
+        option_id!(["python"], "pip", "version"),
+        option_id!(["pytest"], "level"),
+        option_id!(["pytest"], "timeout"),
+    ]);
+
+    assert_eq!(vec!["DEFAULT", "python"], minimal.sections());
+    assert_eq!(vec!["level"], minimal.option_names("DEFAULT"));
+    assert_eq!(vec!["pip_version"], minimal.option_names("python"));
+
+    let conf = ConfigReader::new(minimal, FromfileExpander::relative_to_cwd());
+    assert_eq!(
+        Some("23.1".to_string()),
+        conf.get_string(&option_id!(["python"], "pip", "version"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some("warn".to_string()),
+        conf.get_string(&option_id!(["pytest"], "level")).unwrap()
+    );
+}
+
+#[test]
+fn test_from_file_invalid_utf8() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("pants.toml");
+    File::create(&path)
+        .unwrap()
+        .write_all(b"[a]\nb = 'caf\xe9'\n")
+        .unwrap();
+
+    let err = ConfigSource::from_file(&path).err().unwrap();
+    assert_eq!(
+        format!(
+            "Config file {} is not valid UTF-8 (first bad byte at offset 12)",
+            path.display()
+        ),
+        err
+    );
+}
+
+#[test]
+fn test_get_string_dict() {
+    let conf = config(
+        "[subprocess]\n\
+         env_vars = { PATH = '/bin', _HOME = '/home', LANG2 = 'C' }\n\
+         bad_env_vars.add = { PATH = '/bin', 'MY-VAR' = 'x' }\n\
+         starts_with_digit = { '2FA' = 'on' }\n\
+         lowercase = { level = 'debug' }\n",
+    );
+
+    assert_eq!(
+        Some(vec![DictEdit {
+            action: DictEditAction::Replace,
+            items: hashmap! {
+                "PATH".to_string() => Val::String("/bin".to_string()),
+                "_HOME".to_string() => Val::String("/home".to_string()),
+                "LANG2".to_string() => Val::String("C".to_string()),
+            },
+        }]),
+        conf.get_string_dict(&option_id!(["subprocess"], "env", "vars"), None)
+            .unwrap()
+    );
+    assert_eq!(
+        "Dict key 'MY-VAR' in [subprocess] bad_env_vars is invalid",
+        conf.get_string_dict(&option_id!(["subprocess"], "bad", "env", "vars"), None)
+            .unwrap_err()
+    );
+    assert_eq!(
+        "Dict key '2FA' in [subprocess] starts_with_digit is invalid",
+        conf.get_string_dict(&option_id!(["subprocess"], "starts", "with", "digit"), None)
+            .unwrap_err()
+    );
+    assert_eq!(
+        None,
+        conf.get_string_dict(&option_id!(["subprocess"], "missing"), None)
+            .unwrap()
+    );
+
+    let lowercase = Regex::new("^[a-z]+$").unwrap();
+    assert!(conf
+        .get_string_dict(&option_id!(["subprocess"], "lowercase"), Some(&lowercase))
+        .is_ok());
+    assert_eq!(
+        "Dict keys 'LANG2', 'PATH', '_HOME' in [subprocess] env_vars are invalid",
+        conf.get_string_dict(&option_id!(["subprocess"], "env", "vars"), Some(&lowercase))
+            .unwrap_err()
+    );
+}
+
+#[test]
+fn test_get_string_dict_references() {
+    let conf = config(
+        "[subprocess]\n\
+         env_vars = { BASE = '/usr/bin', PATH = '%(.BIN)s:%(.BASE)s', BIN = '%(.HOME)s/bin', \
+         HOME = '/home/%(seed1)s', DEBUG = true }\n\
+         layered.add = { PATH = '%(.BASE)s:/bin' }\n\
+         extended.add = { PATH = '%(.PATH)s:/bin' }\n\
+         cyclic = { A = '%(.B)s', B = 'x:%(.C)s', C = '%(.A)s' }\n\
+         unknown = { PATH = '%(.BASE)s' }\n\
+         no_earlier = { PATH = '%(.PATH)s:/bin' }\n\
+         [DEFAULT]\n\
+         layered = { BASE = '/opt/bin' }\n\
+         extended = { PATH = '/usr/bin' }\n",
+    );
+
+    assert_eq!(
+        Ok(Some(vec![DictEdit {
+            action: DictEditAction::Replace,
+            items: hashmap! {
+                "BASE".to_string() => Val::String("/usr/bin".to_string()),
+                "PATH".to_string() => Val::String("/home/seed1val/bin:/usr/bin".to_string()),
+                "BIN".to_string() => Val::String("/home/seed1val/bin".to_string()),
+                "HOME".to_string() => Val::String("/home/seed1val".to_string()),
+                "DEBUG".to_string() => Val::Bool(true),
+            },
+        }])),
+        conf.get_string_dict(&option_id!(["subprocess"], "env", "vars"), None)
+    );
+    // A reference to a key that the edit doesn't set is to the value set by earlier edits.
+    assert_eq!(
+        Ok(Some(vec![
+            DictEdit {
+                action: DictEditAction::Replace,
+                items: hashmap! {
+                    "BASE".to_string() => Val::String("/opt/bin".to_string()),
+                },
+            },
+            DictEdit {
+                action: DictEditAction::Add,
+                items: hashmap! {
+                    "PATH".to_string() => Val::String("/opt/bin:/bin".to_string()),
+                },
+            },
+        ])),
+        conf.get_string_dict(&option_id!(["subprocess"], "layered"), None)
+    );
+    // A reference to a value's own key is to its value as set by earlier edits.
+    assert_eq!(
+        Ok(Some(vec![
+            DictEdit {
+                action: DictEditAction::Replace,
+                items: hashmap! {
+                    "PATH".to_string() => Val::String("/usr/bin".to_string()),
+                },
+            },
+            DictEdit {
+                action: DictEditAction::Add,
+                items: hashmap! {
+                    "PATH".to_string() => Val::String("/usr/bin:/bin".to_string()),
+                },
+            },
+        ])),
+        conf.get_string_dict(&option_id!(["subprocess"], "extended"), None)
+    );
+    assert_eq!(
+        Err(
+            "The value of `PATH` in [subprocess] no_earlier references its earlier value, but no \
+            earlier edit sets it"
+                .to_string()
+        ),
+        conf.get_string_dict(&option_id!(["subprocess"], "no", "earlier"), None)
+    );
+    assert_eq!(
+        Err(
+            "Values of [subprocess] cyclic reference each other in a cycle: A -> B -> C -> A"
+                .to_string()
+        ),
+        conf.get_string_dict(&option_id!(["subprocess"], "cyclic"), None)
+    );
+    assert_eq!(
+        Err("Unknown key `BASE` referenced in [subprocess] unknown".to_string()),
+        conf.get_string_dict(&option_id!(["subprocess"], "unknown"), None)
+    );
+}
+
+#[test]
+fn test_profile_list_values() {
+    let content = "[DEFAULT]\n\
+                   \"extra_requirements@ci\" = ['ci-reporter']\n\
+                   [python]\n\
+                   extra_requirements = ['requests']\n\
+                   \"extra_requirements@ci\" = ['pytest-xdist']\n\
+                   \"extra_requirements@dev\" = ['ipdb']\n\
+                   [pytest]\n\
+                   \"args@ci\" = ['-x']\n\
+                   \"bad@ci\" = 'not-an-array'\n";
+    let python_reqs = option_id!(["python"], "extra", "requirements");
+    let add = |items: Vec<&str>| ListEdit {
+        action: ListEditAction::Add,
+        items: items.into_iter().map(str::to_string).collect(),
+    };
+    let replace = |items: Vec<&str>| ListEdit {
+        action: ListEditAction::Replace,
+        items: items.into_iter().map(str::to_string).collect(),
+    };
+
+    let conf = config(content);
+    assert_eq!(
+        Some(vec![replace(vec!["requests"])]),
+        conf.get_string_list(&python_reqs).unwrap()
+    );
+    assert_eq!(
+        None,
+        conf.get_string_list(&option_id!(["pytest"], "args"))
+            .unwrap()
+    );
+
+    let conf = config(content).with_active_profile("ci");
+    assert_eq!(
+        Some(vec![
+            add(vec!["ci-reporter"]),
+            replace(vec!["requests"]),
+            add(vec!["pytest-xdist"])
+        ]),
+        conf.get_string_list(&python_reqs).unwrap()
+    );
+    assert_eq!(
+        Some(vec![add(vec!["-x"])]),
+        conf.get_string_list(&option_id!(["pytest"], "args"))
+            .unwrap()
+    );
+    assert_eq!(
+        "Expected bad@ci to be a toml array or Python sequence, but given \"not-an-array\".",
+        conf.get_string_list(&option_id!(["pytest"], "bad"))
+            .unwrap_err()
+    );
+}
+
+#[test]
+fn test_get_string_list_flat() {
+    let conf = config(
+        "[DEFAULT]\n\
+         tags = ['a', 'b']\n\
+         [scope]\n\
+         tags = \"+['c', 'd'],-['a']\"\n\
+         [other]\n\
+         tags = { clear = true, add = ['e'] }\n",
+    );
+    let flat = |edits: &[(ListEditAction, &str)]| {
+        Ok(Some(
+            edits
+                .iter()
+                .map(|(action, item)| (*action, item.to_string()))
+                .collect::<Vec<_>>(),
+        ))
+    };
+
+    assert_eq!(
+        flat(&[
+            (ListEditAction::Replace, "a"),
+            (ListEditAction::Replace, "b"),
+            (ListEditAction::Add, "c"),
+            (ListEditAction::Add, "d"),
+            (ListEditAction::Remove, "a"),
+        ]),
+        conf.get_string_list_flat(&option_id!(["scope"], "tags"))
+    );
+    // The clear has no elements, so appears nowhere in the stream.
+    assert_eq!(
+        flat(&[
+            (ListEditAction::Replace, "a"),
+            (ListEditAction::Replace, "b"),
+            (ListEditAction::Add, "e"),
+        ]),
+        conf.get_string_list_flat(&option_id!(["other"], "tags"))
+    );
+    assert_eq!(
+        Ok(None),
+        conf.get_string_list_flat(&option_id!(["scope"], "missing"))
+    );
+}
+
+#[test]
+fn test_get_string_list_matching() {
+    let conf = config(
+        "[DEFAULT]\n\
+         modules = ['pants.base', 'pants.Option']\n\
+         [python]\n\
+         modules = { add = ['pants.util', 'pants.engine'], remove = ['pants.Option'] }\n\
+         [pytest]\n\
+         modules.add = ['pants.engine.internals', 'pants/bad']\n",
+    );
+    let pattern = Regex::new(r"^[a-z_]+(\.[a-z_]+)*$").unwrap();
+
+    assert_eq!(
+        Some(vec![
+            "pants.base".to_string(),
+            "pants.util".to_string(),
+            "pants.engine".to_string(),
+        ]),
+        conf.get_string_list_matching(&option_id!(["python"], "modules"), &pattern)
+            .unwrap()
+    );
+    assert_eq!(
+        "Expected every element of [pytest] modules to match ^[a-z_]+(\\.[a-z_]+)*$ but element 1 \
+         was pants.Option",
+        conf.get_string_list_matching(&option_id!(["pytest"], "modules"), &pattern)
+            .unwrap_err()
+    );
+    assert_eq!(
+        None,
+        conf.get_string_list_matching(&option_id!(["pytest"], "missing"), &pattern)
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_validate_reserved_sections() {
+    assert!(
+        parsed_config("[DEFAULT]\na = 1\n[GLOBAL]\nb = 2\n[python]\nc = 3\n")
+            .validate_reserved_sections()
+            .is_ok()
+    );
+
+    for (section_name, reserved_name) in [
+        ("DEFALT", "DEFAULT"),
+        ("Globl", "GLOBAL"),
+        ("_lockd", "_locked"),
+    ] {
+        assert_eq!(
+            format!(
+                "Config section [{section_name}] looks like a misspelling of the reserved section \
+                 [{reserved_name}]: did you mean [{reserved_name}]?"
+            ),
+            parsed_config(&format!("[{section_name}]\na = 1\n"))
+                .validate_reserved_sections()
+                .unwrap_err()
+        );
+    }
+
+    assert_eq!(
+        "Config section [default] collides with the reserved section [DEFAULT]: rename the \
+         default scope, or use [DEFAULT] if the reserved section was intended",
+        parsed_config("[default]\na = 1\n")
+            .validate_reserved_sections()
+            .unwrap_err()
+    );
+    assert_eq!(
+        "Config section [_LOCKED] collides with the reserved section [_locked]: rename the \
+         _LOCKED scope, or use [_locked] if the reserved section was intended",
+        parsed_config("[_LOCKED]\noptions = ['python.pip_version']\n")
+            .validate_reserved_sections()
+            .unwrap_err()
+    );
+    assert!(
+        parsed_config("[_locked]\noptions = ['python.pip_version']\n")
+            .validate_reserved_sections()
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_sanitized() {
+    let conf = parsed_config(
+        "[auth]\n\
+         user = 'me'\n\
+         token = 'hunter2'\n\
+         password = { from_env = 'PASSWORD' }\n\
+         [subprocess-environment]\n\
+         env_vars = ['PATH', '@secret:AWS_KEY=abc']\n\
+         [python]\n\
+         indexes = { private = '@secret:https://me:pw@x.io', public = 'https://pypi.org' }\n",
+    );
+    let secret_keys = HashSet::from(["auth.token".to_string()]);
+
+    assert_eq!(
+        "{\"auth\":{\"password\":\"***\",\"token\":\"***\",\"user\":\"me\"},\
+         \"python\":{\"indexes\":{\"private\":\"***\",\"public\":\"https://pypi.org\"}},\
+         \"subprocess-environment\":{\"env_vars\":[\"PATH\",\"***\"]}}",
+        conf.sanitized(&secret_keys).to_canonical_json()
+    );
+    // The original is unchanged.
+    assert!(conf.to_canonical_json().contains("hunter2"));
+}
+
+#[test]
+fn test_example_sections() {
+    let content = "[python]\n\
+                   resolver = 'pip'\n\
+                   \n\
+                   # @pants: example\n\
+                   # Uncomment to use a private index.\n\
+                   [python-repos]\n\
+                   indexes = ['%(private_index)s']\n\
+                   \n\
+                   # @pants: example\n\
+                   ['python.lint']\n\
+                   skip = true\n\
+                   \n\
+                   # @pants: owner build-infra\n\
+                   [pytest]\n\
+                   args = ['-x']\n";
+    assert_eq!(vec!["pytest", "python"], parsed_config(content).sections());
+
+    let conf = config(content);
+    assert_eq!(
+        Ok(None),
+        conf.get_string_list(&option_id!(["python-repos"], "indexes"))
+    );
+    assert_eq!(
+        Ok(None),
+        conf.get_bool(&option_id!(["python.lint"], "skip"))
+    );
+    assert_eq!(
+        Ok(Some("pip".to_string())),
+        conf.get_string(&option_id!(["python"], "resolver"))
+    );
+}
+
+#[test]
+fn test_deprecated_values() {
+    let reported = Arc::new(Mutex::new(vec![]));
+    let mut conf =
+        parsed_config("[cc]\ncompiler = 'gcc'\nlinker = 'gcc'\n[rust]\ncompiler = 'rustc'\n");
+    conf.set_warning_sink({
+        let reported = reported.clone();
+        Arc::new(move |severity, message| {
+            reported
+                .lock()
+                .unwrap()
+                .push((severity, message.to_string()))
+        })
+    });
+    let value_map = HashMap::from([("gcc".to_string(), "clang".to_string())]);
+    let conf = ConfigReader::new(conf, FromfileExpander::relative_to_cwd())
+        .with_deprecated_values(option_id!(["cc"], "compiler"), value_map.clone())
+        .with_deprecated_values(option_id!(["rust"], "compiler"), value_map);
+
+    assert_eq!(
+        Ok(Some("clang".to_string())),
+        conf.get_string(&option_id!(["cc"], "compiler"))
+    );
+    // Unmapped values, and the values of other options, pass through.
+    assert_eq!(
+        Ok(Some("rustc".to_string())),
+        conf.get_string(&option_id!(["rust"], "compiler"))
+    );
+    assert_eq!(
+        Ok(Some("gcc".to_string())),
+        conf.get_string(&option_id!(["cc"], "linker"))
+    );
+    assert_eq!(
+        vec![(
+            Severity::Warning,
+            "Value 'gcc' for [cc] compiler is deprecated; using 'clang'".to_string()
+        )],
+        *reported.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_warning_sink() {
+    let reported = Arc::new(Mutex::new(vec![]));
+    let mut conf = parsed_config("[python]\n[pytest]\nargs.add = ['-v']\n");
+    conf.set_warning_sink({
+        let reported = reported.clone();
+        Arc::new(move |severity, message| {
+            reported
+                .lock()
+                .unwrap()
+                .push((severity, message.to_string()))
+        })
+    });
+
+    let conf = conf
+        .overlay("[pytest]\nargs = ['-x']\n", "pants.ci.toml")
+        .unwrap()
+        .with_empty_section_warnings();
+    assert_eq!(
+        vec![
+            (
+                Severity::Warning,
+                "Replace of [pytest] args in pants.ci.toml discards list edits from pants.toml"
+                    .to_string()
+            ),
+            (
+                Severity::Info,
+                "Config section [python] contains no options".to_string()
+            ),
+        ],
+        *reported.lock().unwrap()
+    );
+    // Warnings are still collected.
+    assert_eq!(2, conf.warnings().len());
+}
+
+#[test]
+fn test_non_default_options() {
+    let conf = parsed_config(
+        "[DEFAULT]\n\
+         resolver = 'uv'\n\
+         timeout = 30\n\
+         [GLOBAL]\n\
+         level = 'info'\n\
+         pantsd = false\n\
+         [python]\n\
+         pip_version = '23.1'\n\
+         timeout = 60\n\
+         unregistered = 1\n",
+    );
+    let defaults = HashMap::from([
+        (option_id!("level"), Value::from("info")),
+        (option_id!("pantsd"), Value::from(true)),
+        (option_id!("colors"), Value::from(true)),
+        (
+            option_id!(["python"], "pip", "version"),
+            Value::from("24.0"),
+        ),
+        (option_id!(["python"], "resolver"), Value::from("pip")),
+        (option_id!(["python"], "timeout"), Value::from(30)),
+    ]);
+    assert_eq!(
+        vec![
+            ("[GLOBAL] pantsd".to_string(), Value::from(false)),
+            ("[python] pip_version".to_string(), Value::from("23.1")),
+            ("[python] resolver".to_string(), Value::from("uv")),
+            ("[python] timeout".to_string(), Value::from(60)),
+        ],
+        conf.non_default_options(&defaults)
+    );
+}
+
+#[test]
+fn test_canonicalize() {
+    let conf = parsed_config(
+        "[DEFAULT]\n\
+         tags.add = ['shared']\n\
+         [python]\n\
+         interpreter_constraints.add = ['>=3.11']\n\
+         interpreter_constraints.remove = ['>=3.8']\n\
+         env_vars.add = { PATH = '/bin' }\n\
+         pip_version = '23.1'\n\
+         [pytest]\n\
+         args = { clear = true, add = ['-x'] }\n",
+    );
+    let defaults = HashMap::from([
+        (
+            option_id!(["python"], "interpreter", "constraints"),
+            Value::from(vec![">=3.8", ">=3.9"]),
+        ),
+        (
+            option_id!(["python"], "env", "vars"),
+            Value::Table(toml::map::Map::from_iter([(
+                "HOME".to_owned(),
+                Value::from("/home"),
+            )])),
+        ),
+        (option_id!(["pytest"], "tags"), Value::from(vec!["test"])),
+    ]);
+    let expected = parsed_config(
+        "[DEFAULT]\n\
+         tags = ['shared']\n\
+         [python]\n\
+         interpreter_constraints = ['>=3.9', '>=3.11']\n\
+         env_vars = { HOME = '/home', PATH = '/bin' }\n\
+         pip_version = '23.1'\n\
+         [pytest]\n\
+         args = ['-x']\n\
+         tags = ['test', 'shared']\n",
+    );
+    assert_eq!(
+        Vec::<ConfigChange>::new(),
+        conf.canonicalize(&defaults, &FromfileExpander::relative_to_cwd())
+            .unwrap()
+            .diff(&expected)
+    );
+}
+
+#[test]
+fn test_canonicalize_from_file() {
+    let (_tmpdir, fromfile_path) = write_fromfile("args.json", "[\"-x\", \"-v\", \"-s\"]");
+    let conf = parsed_config(&format!(
+        "[pytest]\n\
+         args = {{ from_file = '{}', add = ['--lf'], remove = ['-v'] }}\n\
+         optional = {{ from_file = '?/does/not/exist', add = ['a'] }}\n",
+        fromfile_path.display()
+    ));
+    let expected = parsed_config(
+        "[pytest]\n\
+         args = ['-x', '-s', '--lf']\n\
+         optional = ['a']\n",
+    );
+    assert_eq!(
+        Vec::<ConfigChange>::new(),
+        conf.canonicalize(&HashMap::new(), &FromfileExpander::relative_to_cwd())
+            .unwrap()
+            .diff(&expected)
+    );
+
+    // A file that can't be read is an error, as it is when reading the option.
+    let conf = parsed_config("[pytest]\nargs = { from_file = '/does/not/exist', add = ['a'] }\n");
+    assert!(conf
+        .canonicalize(&HashMap::new(), &FromfileExpander::relative_to_cwd())
+        .err()
+        .unwrap()
+        .starts_with(
+            "Problem reading /does/not/exist for [pytest] args: No such file or directory"
+        ));
+    let defaults = HashMap::from([(option_id!(["pytest"], "args"), Value::from(vec!["-v"]))]);
+    assert!(conf
+        .canonicalize(&defaults, &FromfileExpander::relative_to_cwd())
+        .err()
+        .unwrap()
+        .starts_with(
+            "Problem reading /does/not/exist for [pytest] args: No such file or directory"
+        ));
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_get_path_list() {
+    let conf = config(
+        "[python]\n\
+         search_path = '/usr/bin:~/bin::tools'\n\
+         extra_paths.add = ['~/lib', '/opt/lib']\n",
+    );
+    let home_path = |path: &str| shellexpand::tilde(path).into_owned();
+
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec![
+                "/usr/bin".to_string(),
+                home_path("~/bin"),
+                "tools".to_string()
+            ],
+        }]),
+        conf.get_path_list(&option_id!(["python"], "search", "path"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Add,
+            items: vec![home_path("~/lib"), "/opt/lib".to_string()],
+        }]),
+        conf.get_path_list(&option_id!(["python"], "extra", "paths"))
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_paths_relative_to_config() {
+    let dir = TempDir::new().unwrap();
+    let write_config = |root: &str, content: &str| {
+        let root_dir = dir.path().join(root);
+        std::fs::create_dir(&root_dir).unwrap();
+        let path = root_dir.join("pants.toml");
+        File::create(&path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+        Config::parse(&ConfigSource::from_file(&path).unwrap(), &HashMap::new()).unwrap()
+    };
+    let config_a = write_config(
+        "a",
+        "[a]\n\
+         cache = 'cache'\n\
+         search_path = ['bin', '/usr/bin']\n",
+    );
+    let config_b = write_config(
+        "b",
+        "[b]\n\
+         cache = 'work/cache'\n\
+         [a]\n\
+         search_path.add = ['tools']\n",
+    );
+    let conf = ConfigReader::new(
+        config_a.merge(&config_b),
+        FromfileExpander::relative_to_cwd(),
+    )
+    .with_paths_relative_to_config();
+    let path =
+        |root: &str, relpath: &str| dir.path().join(root).join(relpath).display().to_string();
+
+    assert_eq!(
+        Some(path("a", "cache")),
+        conf.get_path(&option_id!(["a"], "cache")).unwrap()
+    );
+    assert_eq!(
+        Some(path("b", "work/cache")),
+        conf.get_path(&option_id!(["b"], "cache")).unwrap()
+    );
+    assert_eq!(None, conf.get_path(&option_id!(["b"], "missing")).unwrap());
+    // The merged value was set by the second file, so is resolved relative to it.
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Add,
+            items: vec![path("b", "tools")],
+        }]),
+        conf.get_path_list(&option_id!(["a"], "search", "path"))
+            .unwrap()
+    );
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec![path("a", "bin"), "/usr/bin".to_string()],
+        }]),
+        ConfigReader::new(config_a, FromfileExpander::relative_to_cwd())
+            .with_paths_relative_to_config()
+            .get_path_list(&option_id!(["a"], "search", "path"))
+            .unwrap()
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_load_user_config() {
+    let dir = TempDir::new().unwrap();
+    let home = dir.path().join("home");
+    let xdg_config_home = dir.path().join("xdg");
+    let env_var = |name: &str| match name {
+        "HOME" => Some(home.display().to_string()),
+        "XDG_CONFIG_HOME" => Some(xdg_config_home.display().to_string()),
+        _ => None,
+    };
+
+    let paths = user_config_paths(env_var);
+    assert_eq!(
+        Some(&xdg_config_home.join("pants/config.toml")),
+        paths.first()
+    );
+    assert_eq!(Some(&home.join(".config/pants/config.toml")), paths.last());
+    assert!(Config::load_user_config_from(env_var).unwrap().is_none());
+
+    let write_config = |path: &PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    };
+    let get_level = |config: Config| {
+        ConfigReader::new(config, FromfileExpander::relative_to_cwd())
+            .get_string(&option_id!("level"))
+            .unwrap()
+    };
+
+    write_config(
+        &home.join(".config/pants/config.toml"),
+        "[GLOBAL]\nlevel = 'debug'\n",
+    );
+    let user_config = Config::load_user_config_from(env_var).unwrap().unwrap();
+    assert_eq!(Some("debug".to_string()), get_level(user_config));
+
+    write_config(
+        &xdg_config_home.join("pants/config.toml"),
+        "[GLOBAL]\nlevel = 'warn'\n",
+    );
+    let user_config = Config::load_user_config_from(env_var).unwrap().unwrap();
+    assert_eq!(Some("warn".to_string()), get_level(user_config));
+}
@@ -8,7 +8,7 @@ use std::ffi::OsString;
 use super::id::{NameTransform, OptionId, Scope};
 use super::{DictEdit, OptionsSource};
 use crate::fromfile::FromfileExpander;
-use crate::parse::Parseable;
+use crate::parse::{parse_env_bool, Parseable};
 use crate::ListEdit;
 
 #[derive(Debug)]
@@ -127,7 +127,7 @@ impl OptionsSource for EnvReader {
 
     fn get_bool(&self, id: &OptionId) -> Result<Option<bool>, String> {
         if let Some(value) = self.get_string(id)? {
-            bool::parse(&value)
+            parse_env_bool(&value)
                 .map(Some)
                 .map_err(|e| e.render(self.display(id)))
         } else {
@@ -107,6 +107,8 @@ fn test_bool() {
         ("PANTS_FOO", "true"),
         ("PANTS_BAR_BAZ", "False"),
         ("PANTS_EGGS", "swallow"),
+        ("PANTS_SPAM", "ON"),
+        ("PANTS_HAM", ""),
     ]);
 
     let assert_bool =
@@ -114,11 +116,13 @@ fn test_bool() {
 
     assert_bool(true, option_id!("foo"));
     assert_bool(false, option_id!("bar", "baz"));
+    assert_bool(true, option_id!("spam"));
+    assert_bool(false, option_id!("ham"));
 
     assert!(env.get_bool(&option_id!("dne")).unwrap().is_none());
     assert_eq!(
-        "Problem parsing PANTS_EGGS bool value:\n1:swallow\n  ^\nExpected 'true' or 'false' \
-        at line 1 column 1"
+        "Problem parsing PANTS_EGGS bool value: expected one of 1, true, yes or on for true, or \
+        one of 0, false, no, off or the empty string for false, but given 'swallow'"
             .to_owned(),
         env.get_bool(&option_id!("pants", "eggs")).unwrap_err()
     );
@@ -131,6 +135,7 @@ fn test_float() {
         ("PANTS_BAR_BAZ", "3.14"),
         ("PANTS_EGGS", "1.137"),
         ("PANTS_BAD", "swallow"),
+        ("PANTS_TRAILING_DOT", "0."),
     ]);
 
     let assert_float =
@@ -148,6 +153,12 @@ fn test_float() {
             .to_owned(),
         env.get_float(&option_id!("pants", "bad")).unwrap_err()
     );
+    assert_eq!(
+        "Problem parsing PANTS_TRAILING_DOT float value:\n1:0.\n  --^\n\
+        Expected ['0'..='9'] at line 1 column 3"
+            .to_owned(),
+        env.get_float(&option_id!("trailing", "dot")).unwrap_err()
+    );
 }
 
 #[test]
@@ -129,6 +129,12 @@ impl FromfileExpander {
         }
     }
 
+    // Expands a leading `~` in `path` to the home directory, and treats the result as relative
+    // to the same directory as fromfile relpaths are if it is a relpath.
+    pub(crate) fn expand_path(&self, path: &str) -> PathBuf {
+        self.build_root.join(shellexpand::tilde(path).as_ref())
+    }
+
     pub(crate) fn expand_to_dict(
         &self,
         value: String,
@@ -7,7 +7,7 @@ use std::fmt::{Display, Formatter};
 
 use regex::Regex;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Scope {
     Global,
     Scope(String),
@@ -40,7 +40,7 @@ impl Scope {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct OptionId {
     pub(crate) scope: Scope,
     pub(crate) name_components: Vec<String>,
@@ -25,6 +25,10 @@ mod id;
 #[cfg(test)]
 mod id_tests;
 
+mod map_source;
+#[cfg(test)]
+mod map_source_tests;
+
 mod parse;
 #[cfg(test)]
 mod parse_tests;
@@ -44,15 +48,19 @@ use serde::Deserialize;
 
 pub use self::args::Args;
 use self::args::ArgsReader;
-pub use self::config::ConfigSource;
-use self::config::{Config, ConfigReader};
+pub use self::config::{
+    Config, ConfigChange, ConfigReader, ConfigSource, LintWarning, OptionMetadata, Relation,
+    SectionCase, Severity, UnsetReason, CONFIG_VERSION,
+};
 pub use self::env::Env;
 use self::env::EnvReader;
-use crate::fromfile::FromfileExpander;
-use crate::parse::Parseable;
+pub use crate::fromfile::FromfileExpander;
+pub use crate::parse::parse_as_toml_scalar;
+use crate::parse::{parse_toml_like_number, Parseable};
 pub use build_root::BuildRoot;
 pub use id::{OptionId, Scope};
-pub use types::OptionType;
+pub use map_source::MapSource;
+pub use types::{OptionSpec, OptionType, Resolvable};
 
 // NB: The legacy Python options parser supported dicts with member_type "Any", which means
 // the values can be arbitrarily-nested lists, tuples and dicts, including heterogeneous
@@ -79,6 +87,8 @@ pub enum ListEditAction {
     Replace,
     Add,
     Remove,
+    // Discards all items accumulated so far. Its edit carries no items.
+    Clear,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -87,6 +97,15 @@ pub struct ListEdit<T> {
     pub items: Vec<T>,
 }
 
+// Which of the occurrences of a duplicated item a list keeps when it is deduplicated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Dedup {
+    // Keep the first occurrence, so re-adding an item leaves it where it was.
+    FirstWins,
+    // Keep the last occurrence, so re-adding an item moves it later, as for PATH-like precedence.
+    LastWins,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum DictEditAction {
     Replace,
@@ -99,7 +118,11 @@ pub struct DictEdit {
     pub items: HashMap<String, Val>,
 }
 
-pub(crate) trait OptionsSource: Send + Sync {
+///
+/// A source of option values, such as flags, env vars or a config file. This is public so that
+/// callers can read the public sources, `ConfigReader` and `MapSource`, directly.
+///
+pub trait OptionsSource: Send + Sync {
     ///
     /// Get a display version of the option `id` that most closely matches the syntax used to supply
     /// the id at runtime. For example, an global option of "bob" would display as "--bob" for use in
@@ -146,16 +169,15 @@ pub(crate) trait OptionsSource: Send + Sync {
     ///
     fn get_float(&self, id: &OptionId) -> Result<Option<f64>, String> {
         if let Some(value) = self.get_string(id)? {
-            let parsed_as_float = f64::parse(&value)
-                .map(Some)
-                .map_err(|e| e.render(self.display(id)));
-            if parsed_as_float.is_err() {
-                // See if we can parse as an int and coerce it to a float.
-                if let Ok(i) = i64::parse(&value) {
-                    return Ok(Some(i as f64));
-                }
+            match parse_toml_like_number(&value) {
+                Ok(Val::Float(f)) => Ok(Some(f)),
+                // An int is coerced to a float.
+                Ok(Val::Int(i)) => Ok(Some(i as f64)),
+                // Parse as a float for the sake of the error message.
+                _ => f64::parse(&value)
+                    .map(Some)
+                    .map_err(|e| e.render(self.display(id))),
             }
-            parsed_as_float
         } else {
             Ok(None)
         }
@@ -264,6 +286,14 @@ pub struct DictOptionValue {
     pub value: HashMap<String, Val>,
 }
 
+// A single list edit as applied while resolving a list option, along with the source it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppliedListEdit<T> {
+    pub source: Source,
+    pub action: ListEditAction,
+    pub items: Vec<T>,
+}
+
 pub struct OptionParser {
     sources: BTreeMap<Source, Arc<dyn OptionsSource>>,
     include_derivation: bool,
@@ -511,7 +541,34 @@ impl OptionParser {
         id: &OptionId,
         default: Vec<T>,
         getter: fn(&Arc<dyn OptionsSource>, &OptionId) -> Result<Option<Vec<ListEdit<T>>>, String>,
-        remover: fn(&mut Vec<T>, &Vec<T>),
+        remover: impl Fn(&mut Vec<T>, &Vec<T>) -> Result<(), String>,
+    ) -> Result<ListOptionValue<T>, String> {
+        let source_edits = self.get_list_edits(id, getter)?;
+        self.fold_list_edits(default, source_edits, remover)
+    }
+
+    // Reads the list edits of `id` from each source that sets it, in the order that sources apply.
+    #[allow(clippy::type_complexity)]
+    fn get_list_edits<T>(
+        &self,
+        id: &OptionId,
+        getter: fn(&Arc<dyn OptionsSource>, &OptionId) -> Result<Option<Vec<ListEdit<T>>>, String>,
+    ) -> Result<Vec<(Source, Vec<ListEdit<T>>)>, String> {
+        let mut source_edits = vec![];
+        for (source_type, source) in self.sources.iter() {
+            if let Some(list_edits) = getter(source, id)? {
+                source_edits.push((source_type.clone(), list_edits));
+            }
+        }
+        Ok(source_edits)
+    }
+
+    // Applies the list edits read from each source by `get_list_edits` to `default`.
+    fn fold_list_edits<T: Clone + Debug>(
+        &self,
+        default: Vec<T>,
+        source_edits: Vec<(Source, Vec<ListEdit<T>>)>,
+        remover: impl Fn(&mut Vec<T>, &Vec<T>) -> Result<(), String>,
     ) -> Result<ListOptionValue<T>, String> {
         let mut list = default;
         let mut derivation = None;
@@ -523,13 +580,12 @@ impl OptionParser {
                     items: list.clone(),
                 }],
             )];
-            for (source_type, source) in self.sources.iter() {
-                if let Some(list_edits) = getter(source, id)? {
-                    if !list_edits.is_empty() {
-                        derivations.push((source_type.clone(), list_edits));
-                    }
-                }
-            }
+            derivations.extend(
+                source_edits
+                    .iter()
+                    .filter(|(_, list_edits)| !list_edits.is_empty())
+                    .cloned(),
+            );
             derivation = Some(derivations);
         }
 
@@ -538,23 +594,25 @@ impl OptionParser {
         let mut removal_lists: Vec<Vec<T>> = vec![];
 
         let mut highest_priority_source = Source::Default;
-        for (source_type, source) in self.sources.iter() {
-            if let Some(list_edits) = getter(source, id)? {
-                highest_priority_source = source_type.clone();
-                for list_edit in list_edits {
-                    match list_edit.action {
-                        ListEditAction::Replace => {
-                            list = list_edit.items;
-                            removal_lists.clear();
-                        }
-                        ListEditAction::Add => list.extend(list_edit.items),
-                        ListEditAction::Remove => removal_lists.push(list_edit.items),
+        for (source_type, list_edits) in source_edits {
+            highest_priority_source = source_type;
+            for list_edit in list_edits {
+                match list_edit.action {
+                    ListEditAction::Replace => {
+                        list = list_edit.items;
+                        removal_lists.clear();
+                    }
+                    ListEditAction::Add => list.extend(list_edit.items),
+                    ListEditAction::Remove => removal_lists.push(list_edit.items),
+                    ListEditAction::Clear => {
+                        list.clear();
+                        removal_lists.clear();
                     }
                 }
             }
         }
         for removals in removal_lists {
-            remover(&mut list, &removals);
+            remover(&mut list, &removals)?;
         }
         Ok(ListOptionValue {
             derivation,
@@ -577,11 +635,16 @@ impl OptionParser {
         getter: fn(&Arc<dyn OptionsSource>, &OptionId) -> Result<Option<Vec<ListEdit<T>>>, String>,
     ) -> Result<ListOptionValue<T>, String> {
         self.parse_list(id, default, getter, |list, remove| {
-            let to_remove = remove.iter().collect::<HashSet<_>>();
-            list.retain(|item| !to_remove.contains(item));
+            Self::remove_hashable(list, remove)
         })
     }
 
+    fn remove_hashable<T: Eq + Hash>(list: &mut Vec<T>, remove: &[T]) -> Result<(), String> {
+        let to_remove = remove.iter().collect::<HashSet<_>>();
+        list.retain(|item| !to_remove.contains(item));
+        Ok(())
+    }
+
     pub fn parse_bool_list(
         &self,
         id: &OptionId,
@@ -610,6 +673,7 @@ impl OptionParser {
             |source, id| source.get_float_list(id),
             |list, to_remove| {
                 list.retain(|item| !to_remove.contains(item));
+                Ok(())
             },
         )
     }
@@ -622,6 +686,128 @@ impl OptionParser {
         self.parse_list_hashable::<String>(id, default, |source, id| source.get_string_list(id))
     }
 
+    ///
+    /// Parses the string list option identified by `id` as `parse_string_list` does, but with a
+    /// stricter policy: duplicate items collapse into the first or the last of them, according
+    /// to `dedup`, and removing an item that is not present is an error, since a dangling removal
+    /// in these options signals a bug.
+    ///
+    pub fn parse_string_list_strict(
+        &self,
+        id: &OptionId,
+        default: Vec<String>,
+        dedup: Dedup,
+    ) -> Result<ListOptionValue<String>, String> {
+        let mut list_value = self.parse_list(
+            id,
+            default,
+            |source, id| source.get_string_list(id),
+            |list, to_remove| {
+                for item in to_remove {
+                    if !list.contains(item) {
+                        return Err(format!(
+                            "Expected to remove '{item}' from {id} but it is not present"
+                        ));
+                    }
+                    list.retain(|existing| existing != item);
+                }
+                Ok(())
+            },
+        )?;
+        let mut seen = HashSet::new();
+        match dedup {
+            Dedup::FirstWins => list_value.value.retain(|item| seen.insert(item.clone())),
+            Dedup::LastWins => {
+                list_value.value.reverse();
+                list_value.value.retain(|item| seen.insert(item.clone()));
+                list_value.value.reverse();
+            }
+        }
+        Ok(list_value)
+    }
+
+    ///
+    /// Parses a set of enabled flags from the string list option identified by `id`, where each
+    /// item is a comma-separated list of flag names such as `"flag_a,-flag_b"`. Starting from the
+    /// `default` flags, each name enables that flag, and each name prefixed with `-` disables it.
+    ///
+    /// Errors if any name is not one of `known_flags`.
+    ///
+    pub fn parse_flag_set(
+        &self,
+        id: &OptionId,
+        default: &[&str],
+        known_flags: &[&str],
+    ) -> Result<HashSet<String>, String> {
+        let items = self
+            .parse_string_list(id, default.iter().map(|flag| flag.to_string()).collect())?
+            .value;
+        let mut flags = HashSet::new();
+        for name in items
+            .iter()
+            .flat_map(|item| item.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let (enable, flag) = match name.strip_prefix('-') {
+                Some(flag) => (false, flag),
+                None => (true, name.strip_prefix('+').unwrap_or(name)),
+            };
+            if !known_flags.contains(&flag) {
+                return Err(format!(
+                    "Unknown flag `{flag}` for {id}: expected {}",
+                    render_choice(known_flags).unwrap_or_else(|| "no flags".to_owned())
+                ));
+            }
+            if enable {
+                flags.insert(flag.to_owned());
+            } else {
+                flags.remove(flag);
+            }
+        }
+        Ok(flags)
+    }
+
+    ///
+    /// Resolves the string list option identified by `id` as `parse_string_list` does, also
+    /// returning a trace of every list edit in the order it was applied, starting with the
+    /// replacement by `default`. Tooling can inspect the trace for surprising sequences of edits,
+    /// such as a clear that nothing is added after, or a removal of an item that was never added.
+    ///
+    /// The order of the resolved list is stable: it starts with the items of the last replacement
+    /// (the default, unless some source replaced it), followed by the items added by each later
+    /// source in the order that sources are applied: each config file in the order given, then the
+    /// environment, then flags. Within a config file, items added in the DEFAULT section precede
+    /// those added in the scope's own section, and each edit's items keep the order they were
+    /// written in. Removals are applied last, and so remove matching items regardless of which
+    /// source added them, preserving the order of the items that remain.
+    ///
+    pub fn resolve_string_list(
+        &self,
+        id: &OptionId,
+        default: Vec<String>,
+    ) -> Result<(ListOptionValue<String>, Vec<AppliedListEdit<String>>), String> {
+        // Each source is read just once, so that the trace is of exactly the edits that produce
+        // the value, even for sources whose values have side effects or change between reads.
+        let source_edits = self.get_list_edits(id, |source, id| source.get_string_list(id))?;
+        let mut trace = vec![AppliedListEdit {
+            source: Source::Default,
+            action: ListEditAction::Replace,
+            items: default.clone(),
+        }];
+        for (source_type, list_edits) in &source_edits {
+            trace.extend(list_edits.iter().map(|list_edit| AppliedListEdit {
+                source: source_type.clone(),
+                action: list_edit.action,
+                items: list_edit.items.clone(),
+            }));
+        }
+        let list_value = self.fold_list_edits(default, source_edits, |list, remove| {
+            Self::remove_hashable(list, remove)
+        })?;
+        Ok((list_value, trace))
+    }
+
     pub fn parse_dict(
         &self,
         id: &OptionId,
@@ -663,6 +849,15 @@ impl OptionParser {
         })
     }
 
+    ///
+    /// Resolves the value of the option identified by `id` as the type of `spec`, applying the
+    /// value from each source on top of its default. This is equivalent to calling the `parse_*`
+    /// method for that type and taking just the resulting value.
+    ///
+    pub fn resolve<T: Resolvable>(&self, id: &OptionId, spec: &OptionSpec<T>) -> Result<T, String> {
+        T::resolve(self, id, &spec.default)
+    }
+
     pub fn get_passthrough_args(&self) -> Option<&Vec<String>> {
         self.passthrough_args.as_ref()
     }
@@ -0,0 +1,89 @@
+// Copyright 2024 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashMap;
+
+use toml::value::Table;
+use toml::Value;
+
+use super::{DictEdit, ListEdit, OptionsSource};
+use crate::config::{Config, ConfigReader};
+use crate::fromfile::FromfileExpander;
+use crate::id::OptionId;
+
+///
+/// An options source over an in-memory map of values, keyed by section and option name.
+///
+/// Values are interpreted exactly as the same values would be if read from a config file, so a
+/// list option may be given either as an array or as a table of `add`/`remove` edits. This makes
+/// it convenient for supplying defaults programmatically, and for testing.
+///
+/// Only the values themselves are supported, as there is no file for the features that depend on
+/// one: a `_locked` section is read as the section of an ordinary scope rather than locking any
+/// options, and no option has a line for diagnostics to refer to.
+///
+pub struct MapSource {
+    config_reader: ConfigReader,
+}
+
+impl MapSource {
+    pub fn new(
+        values: HashMap<(String, String), Value>,
+        fromfile_expander: FromfileExpander,
+    ) -> Self {
+        let mut table = Table::new();
+        for ((section_name, option_name), value) in values {
+            if let Value::Table(section) = table
+                .entry(section_name)
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                section.insert(option_name, value);
+            }
+        }
+        Self {
+            config_reader: ConfigReader::new(Config::from_table(table, "<map>"), fromfile_expander),
+        }
+    }
+}
+
+impl OptionsSource for MapSource {
+    fn display(&self, id: &OptionId) -> String {
+        self.config_reader.display(id)
+    }
+
+    fn get_string(&self, id: &OptionId) -> Result<Option<String>, String> {
+        self.config_reader.get_string(id)
+    }
+
+    fn get_bool(&self, id: &OptionId) -> Result<Option<bool>, String> {
+        self.config_reader.get_bool(id)
+    }
+
+    fn get_int(&self, id: &OptionId) -> Result<Option<i64>, String> {
+        self.config_reader.get_int(id)
+    }
+
+    fn get_float(&self, id: &OptionId) -> Result<Option<f64>, String> {
+        self.config_reader.get_float(id)
+    }
+
+    fn get_bool_list(&self, id: &OptionId) -> Result<Option<Vec<ListEdit<bool>>>, String> {
+        self.config_reader.get_bool_list(id)
+    }
+
+    fn get_int_list(&self, id: &OptionId) -> Result<Option<Vec<ListEdit<i64>>>, String> {
+        self.config_reader.get_int_list(id)
+    }
+
+    fn get_float_list(&self, id: &OptionId) -> Result<Option<Vec<ListEdit<f64>>>, String> {
+        self.config_reader.get_float_list(id)
+    }
+
+    fn get_string_list(&self, id: &OptionId) -> Result<Option<Vec<ListEdit<String>>>, String> {
+        self.config_reader.get_string_list(id)
+    }
+
+    fn get_dict(&self, id: &OptionId) -> Result<Option<Vec<DictEdit>>, String> {
+        self.config_reader.get_dict(id)
+    }
+}
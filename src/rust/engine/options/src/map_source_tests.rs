@@ -0,0 +1,152 @@
+// Copyright 2024 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use toml::Value;
+
+use crate::config::{Config, ConfigReader, ConfigSource};
+use crate::fromfile::FromfileExpander;
+use crate::map_source::MapSource;
+use crate::{option_id, ListEdit, ListEditAction, OptionId, OptionsSource};
+
+fn map_source(content: &str) -> MapSource {
+    let value = content.parse::<Value>().unwrap();
+    let mut values = HashMap::new();
+    for (section_name, section) in value.as_table().unwrap() {
+        for (option_name, value) in section.as_table().unwrap() {
+            values.insert((section_name.clone(), option_name.clone()), value.clone());
+        }
+    }
+    MapSource::new(values, FromfileExpander::relative_to_cwd())
+}
+
+fn config_reader(content: &str) -> ConfigReader {
+    let config = Config::parse(
+        &ConfigSource {
+            path: PathBuf::from("pants.toml"),
+            content: content.to_string(),
+        },
+        &HashMap::new(),
+    )
+    .unwrap();
+    ConfigReader::new(config, FromfileExpander::relative_to_cwd())
+}
+
+#[test]
+fn test_same_as_config() {
+    let content = "[DEFAULT]\n\
+                   names = ['default']\n\
+                   [GLOBAL]\n\
+                   level = 'info'\n\
+                   enabled = true\n\
+                   jobs = 4\n\
+                   ratio = 0.5\n\
+                   [scope]\n\
+                   names.add = ['scoped']\n\
+                   flags = [true, false]\n\
+                   ids = [1, 2]\n\
+                   weights = { add = [1.5], remove = [2.5] }\n\
+                   env = { add = { FOO = 'bar' } }\n\
+                   jobs = 'many'\n";
+    let map_source = map_source(content);
+    let config_reader = config_reader(content);
+
+    fn assert_same<T: PartialEq + std::fmt::Debug>(
+        get: impl Fn(&dyn OptionsSource, &OptionId) -> Result<T, String>,
+        map_source: &MapSource,
+        config_reader: &ConfigReader,
+        id: OptionId,
+    ) {
+        assert_eq!(get(config_reader, &id), get(map_source, &id), "for {id}");
+    }
+
+    for id in [option_id!("level"), option_id!(["scope"], "level")] {
+        assert_same(|s, id| s.get_string(id), &map_source, &config_reader, id);
+    }
+    assert_same(
+        |s, id| s.get_bool(id),
+        &map_source,
+        &config_reader,
+        option_id!("enabled"),
+    );
+    for id in [option_id!("jobs"), option_id!(["scope"], "jobs")] {
+        assert_same(|s, id| s.get_int(id), &map_source, &config_reader, id);
+    }
+    assert_same(
+        |s, id| s.get_float(id),
+        &map_source,
+        &config_reader,
+        option_id!("ratio"),
+    );
+    for id in [option_id!("names"), option_id!(["scope"], "names")] {
+        assert_same(
+            |s, id| s.get_string_list(id),
+            &map_source,
+            &config_reader,
+            id,
+        );
+    }
+    assert_same(
+        |s, id| s.get_bool_list(id),
+        &map_source,
+        &config_reader,
+        option_id!(["scope"], "flags"),
+    );
+    assert_same(
+        |s, id| s.get_int_list(id),
+        &map_source,
+        &config_reader,
+        option_id!(["scope"], "ids"),
+    );
+    assert_same(
+        |s, id| s.get_float_list(id),
+        &map_source,
+        &config_reader,
+        option_id!(["scope"], "weights"),
+    );
+    assert_same(
+        |s, id| s.get_dict(id),
+        &map_source,
+        &config_reader,
+        option_id!(["scope"], "env"),
+    );
+    assert_same(
+        |s, id| s.get_string(id),
+        &map_source,
+        &config_reader,
+        option_id!(["scope"], "missing"),
+    );
+}
+
+#[test]
+fn test_no_locked_options() {
+    let content = "[_locked]\n\
+                   options = ['python.pip_version']\n\
+                   [python]\n\
+                   pip_version = '23.1'\n";
+    let map_source = map_source(content);
+    assert_eq!(
+        Some("23.1".to_string()),
+        map_source
+            .get_string(&option_id!(["python"], "pip", "version"))
+            .unwrap()
+    );
+    // The `_locked` section is just the section of a scope of that name.
+    assert_eq!(
+        Some(vec![ListEdit {
+            action: ListEditAction::Replace,
+            items: vec!["python.pip_version".to_string()]
+        }]),
+        map_source
+            .get_string_list(&option_id!(["_locked"], "options"))
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        config_reader(content)
+            .get_string_list(&option_id!(["_locked"], "options"))
+            .unwrap()
+    );
+}
@@ -7,6 +7,7 @@ use crate::render_choice;
 use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::ops::RangeInclusive;
 use std::path::Path;
 
 peg::parser! {
@@ -28,19 +29,40 @@ peg::parser! {
         pub(crate) rule bool() -> bool
             = b:(true() / false() / expected!("'true' or 'false'")) { b }
 
-        // Python numeric literals can include digit-separator underscores. It's unlikely
-        // that anyone relies on those in option values, but since the old Python options
-        // system accepted them, we support them here.
+        // Numbers follow TOML's grammar for decimal integers and floats, so that a number given as
+        // a string (e.g. via a flag or env var) is read exactly as the same number written natively
+        // in a config file would be. As in both TOML and Python numeric literals, digits may be
+        // separated by single underscores.
         rule digitpart() -> &'input str
             = dp:$(['0'..='9'] ("_"? ['0'..='9'])*) { dp }
 
+        // The (optionally signed) integer part of a number, which may not have leading zeros.
+        rule intpart() -> &'input str
+            = ip:$(("+" / "-")? digitpart()) {?
+                let digits = ip.trim_start_matches(['+', '-']);
+                if digits.len() > 1 && digits.starts_with('0') {
+                    Err("an integer without leading zeros")
+                } else {
+                    Ok(ip)
+                }
+            }
+
+        rule exponent() -> &'input str
+            = e:$(("e" / "E") ("+" / "-")? digitpart()) { e }
+
         pub(crate) rule int() -> i64
-            = i:$(("+" / "-")?digitpart()) { i.replace('_', "").parse::<i64>().unwrap() }
+            = i:intpart() { i.replace('_', "").parse::<i64>().unwrap() }
 
         pub(crate) rule float() -> f64
-            = f:$(("+" / "-")?digitpart() "." digitpart()? (("e" / "E") ("+" / "-") digitpart())?) {
-            f.replace('_', "").parse::<f64>().unwrap()
-        }
+            = f:$(intpart() (("." digitpart() exponent()?) / exponent())) {
+                f.replace('_', "").parse::<f64>().unwrap()
+            }
+            / quiet!{ f:$(("+" / "-")? ("inf" / "nan")) { f.parse::<f64>().unwrap() } }
+
+        // Note that float() must precede int() so that the integer part of a float is not
+        // interpreted as an int.
+        pub(crate) rule number() -> Val
+            = v:(float_val() / int_val()) { v }
 
         rule unquoted_string() -> String
             = s:(escaped_character() / non_escaped_character())+ { s.into_iter().collect() }
@@ -201,10 +223,9 @@ peg::parser! {
             = empty_string_string_list() / implicit_add(<unquoted_string()>) /
               list_replace(<quoted_string()>) / list_edits(<quoted_string()>)
 
-        // Heterogeneous values embedded in dicts. Note that float_val() must precede int_val() so that
-        // the integer prefix of a float is not interpreted as an int.
+        // Heterogeneous values embedded in dicts.
         rule val() -> Val
-            = v:(bool_val() / float_val() / int_val() / string_val() / list_val() / tuple_val() / dict_val()) {
+            = v:(bool_val() / number() / string_val() / list_val() / tuple_val() / dict_val()) {
             v
         }
 
@@ -327,6 +348,196 @@ fn format_parse_error(
     ))
 }
 
+///
+/// Parses a number given as a string according to TOML's grammar for decimal integers and floats,
+/// as either a `Val::Int` or a `Val::Float`. All string values for numeric options are parsed with
+/// this grammar, regardless of which source provides them.
+///
+pub(crate) fn parse_toml_like_number(value: &str) -> Result<Val, ParseError> {
+    option_value_parser::number(value).map_err(|e| format_parse_error("number", value, e))
+}
+
+///
+/// Infers the type of a string value, such as that of an env var or a flag, by interpreting it as
+/// a TOML scalar: so `true` and `false` are bools, `42` and `0x2a` are ints, `1.5` and `inf` are
+/// floats, and `1979-05-27` and `07:32:00` are datetimes. Anything else, including an empty string,
+/// an array or an inline table, is left as the given string.
+///
+/// To force a value that looks like a scalar of another type to be a string, quote it as a TOML
+/// string, as in `"true"` or `'42'`. Values containing a `#` or a newline are always left as
+/// strings, so that a TOML comment or additional key can't alter the result.
+///
+pub fn parse_as_toml_scalar(value: &str) -> toml::Value {
+    if !value.contains(['#', '\n', '\r']) {
+        if let Ok(toml::Value::Table(mut table)) = format!("value = {value}").parse() {
+            match table.remove("value") {
+                Some(toml::Value::Array(_)) | Some(toml::Value::Table(_)) | None => (),
+                Some(scalar) if table.is_empty() => return scalar,
+                Some(_) => (),
+            }
+        }
+    }
+    toml::Value::String(value.to_owned())
+}
+
+///
+/// Parses the bool value of an env var, case-insensitively: `1`, `true`, `yes` and `on` are true,
+/// while `0`, `false`, `no`, `off` and the empty string are false. This is more permissive than
+/// the bool syntax of other sources, since env vars are commonly set with these conventions.
+///
+pub(crate) fn parse_env_bool(value: &str) -> Result<bool, ParseError> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" | "" => Ok(false),
+        _ => Err(ParseError::new(format!(
+            "Problem parsing {{name}} bool value: expected one of 1, true, yes or on for true, or \
+            one of 0, false, no, off or the empty string for false, but given '{value}'"
+        ))),
+    }
+}
+
+///
+/// Parses a comma-separated list of ints and inclusive ranges of ints, as in `1-3,5,7-9`, into
+/// ranges, with a single int as a range of one. A range's start may not be greater than its end.
+///
+pub(crate) fn parse_int_ranges(value: &str) -> Result<Vec<RangeInclusive<i64>>, String> {
+    let parse_int = |token: &str, int: &str| {
+        int.trim()
+            .parse::<i64>()
+            .map_err(|_| format!("Invalid range '{token}' in '{value}'"))
+    };
+    value
+        .split(',')
+        .map(|token| {
+            let token = token.trim();
+            // Skip the first character when looking for the separator, so that a negative start
+            // isn't mistaken for one.
+            let range = match token.char_indices().skip(1).find(|(_, c)| *c == '-') {
+                Some((i, _)) => parse_int(token, &token[..i])?..=parse_int(token, &token[i + 1..])?,
+                None => {
+                    let int = parse_int(token, token)?;
+                    int..=int
+                }
+            };
+            if range.start() > range.end() {
+                return Err(format!(
+                    "Invalid range '{token}' in '{value}': start is greater than end"
+                ));
+            }
+            Ok(range)
+        })
+        .collect()
+}
+
+///
+/// Evaluates an arithmetic expression, such as `4 * num_cores - 1`, over the given `variables`.
+/// Expressions consist of numbers, variable names, the binary operators `+`, `-`, `*` and `/`
+/// with the usual precedence, unary `-`, and parentheses. There are no function calls.
+///
+pub(crate) fn eval_arithmetic(expr: &str, variables: &HashMap<String, f64>) -> Result<f64, String> {
+    let mut evaluator = ArithmeticEvaluator {
+        chars: expr.chars().collect(),
+        pos: 0,
+        variables,
+    };
+    let value = evaluator.sum()?;
+    match evaluator.peek() {
+        None => Ok(value),
+        Some(c) => Err(evaluator.unexpected(c)),
+    }
+}
+
+// A recursive descent evaluator for `eval_arithmetic`, with a method per level of precedence.
+struct ArithmeticEvaluator<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    variables: &'a HashMap<String, f64>,
+}
+
+impl ArithmeticEvaluator<'_> {
+    // Returns the next non-whitespace character, without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn unexpected(&self, c: char) -> String {
+        format!("Unexpected `{c}` at position {}", self.pos + 1)
+    }
+
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.product()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            value = if op == '*' {
+                value * rhs
+            } else if rhs == 0.0 {
+                return Err("Division by zero".to_owned());
+            } else {
+                value / rhs
+            };
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(-self.factor()?)
+            }
+            Some('(') => {
+                self.pos += 1;
+                let value = self.sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    Some(c) => Err(self.unexpected(c)),
+                    None => Err("Expected `)` at the end of the expression".to_owned()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let token = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                token
+                    .parse()
+                    .map_err(|_| format!("Invalid number `{token}`"))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                self.variables
+                    .get(&name)
+                    .copied()
+                    .ok_or_else(|| format!("Unknown variable `{name}`"))
+            }
+            Some(c) => Err(self.unexpected(c)),
+            None => Err("Unexpected end of expression".to_owned()),
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.chars.get(self.pos).copied().is_some_and(&predicate) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
 pub(crate) fn parse_dict(value: &str) -> Result<DictEdit, ParseError> {
     option_value_parser::dict_edit(value).map_err(|e| format_parse_error("dict", value, e))
 }
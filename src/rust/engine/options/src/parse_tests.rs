@@ -114,6 +114,43 @@ fn test_parse_bool() {
     )
 }
 
+#[test]
+fn test_parse_env_bool() {
+    for input in ["1", "true", "True", "yes", "YES", "on", "On"] {
+        check!(true, parse_env_bool(input));
+    }
+    for input in ["0", "false", "FALSE", "no", "No", "off", "OFF", ""] {
+        check!(false, parse_env_bool(input));
+    }
+
+    assert_eq!(
+        "Problem parsing PANTS_FOO bool value: expected one of 1, true, yes or on for true, or \
+        one of 0, false, no, off or the empty string for false, but given 'y'"
+            .to_owned(),
+        parse_env_bool("y").unwrap_err().render("PANTS_FOO")
+    )
+}
+
+#[test]
+fn test_eval_arithmetic() {
+    let variables = HashMap::from([("num_cores".to_string(), 8.0), ("x_1".to_string(), 2.5)]);
+    let eval = |expr: &str| eval_arithmetic(expr, &variables);
+
+    assert_eq!(Ok(31.0), eval("4*num_cores-1"));
+    assert_eq!(Ok(9.0), eval(" (1 + 2) * 3 "));
+    assert_eq!(Ok(-6.0), eval("2 * -3"));
+    assert_eq!(Ok(-1.25), eval("-x_1 / 2"));
+    assert_eq!(Err("Division by zero".to_string()), eval("1 / (2 - 2)"));
+    assert_eq!(Err("Unknown variable `foo`".to_string()), eval("foo + 1"));
+    assert_eq!(
+        Err("Expected `)` at the end of the expression".to_string()),
+        eval("2 * (3")
+    );
+    assert_eq!(Err("Unexpected `2` at position 3".to_string()), eval("1 2"));
+    assert_eq!(Err("Unexpected `^` at position 2".to_string()), eval("2^3"));
+    assert_eq!(Err("Unexpected end of expression".to_string()), eval(""));
+}
+
 #[test]
 fn test_parse_int() {
     fn check_int(expected: i64, input: &str) {
@@ -141,6 +178,16 @@ fn test_parse_int() {
             .to_owned(),
         i64::parse("12badint").unwrap_err().render("foo")
     );
+    // Ints follow TOML's grammar, so leading zeros (which were once accepted) are an error.
+    for leading_zeros in ["007", "+007", "00"] {
+        let err = i64::parse(leading_zeros).unwrap_err().render("foo");
+        assert!(
+            err.starts_with(&format!(
+                "Problem parsing foo int value:\n1:{leading_zeros}\n"
+            )) && err.contains("an integer without leading zeros"),
+            "Unexpected error: {err}"
+        );
+    }
 }
 
 #[test]
@@ -150,7 +197,6 @@ fn test_parse_float() {
     }
     check_float(0.0, "0.0");
     check_float(0.0, "-0.0");
-    check_float(0.0, "0.");
     check_float(1.0, "1.0");
     check_float(0.1, "0.1");
     check_float(0.01, "+0.01");
@@ -159,6 +205,49 @@ fn test_parse_float() {
     check_float(6.022141793e+23, "6.022141793e+23");
     check_float(5.67123e+11, "567.123e+9");
     check_float(9.1093837e-31, "9.1093837E-31");
+    check_float(1e9, "1e+9");
+    check_float(5e22, "5E22");
+    check_float(f64::INFINITY, "inf");
+    check_float(f64::NEG_INFINITY, "-inf");
+    assert!(f64::parse("+nan").unwrap().is_nan());
+    // Floats follow TOML's grammar, so a trailing `.` (which was once accepted) is an error.
+    assert_eq!(
+        "Problem parsing foo float value:\n1:0.\n  --^\nExpected ['0'..='9'] at line 1 column 3"
+            .to_owned(),
+        f64::parse("0.").unwrap_err().render("foo")
+    );
+    assert!(f64::parse(".5").is_err());
+    assert!(f64::parse("07.5").is_err());
+}
+
+#[test]
+fn test_parse_toml_like_number() {
+    fn check_number(expected: Val, input: &str) {
+        check!(expected, parse_toml_like_number(input));
+    }
+    check_number(Val::Int(0), "0");
+    check_number(Val::Int(0), "-0");
+    check_number(Val::Int(1), "+1");
+    check_number(Val::Int(-17), "-17");
+    check_number(Val::Int(1000), "1_000");
+    check_number(Val::Float(-0.0), "-0.0");
+    check_number(Val::Float(2.5), "+2.5");
+    check_number(Val::Float(1e9), "1e+9");
+    check_number(Val::Float(1e9), "1e9");
+    check_number(Val::Float(-2e-2), "-2E-2");
+    check_number(Val::Float(6.626e-34), "6.626e-34");
+    check_number(Val::Float(224617.445991228), "224_617.445_991_228");
+    check_number(Val::Float(f64::INFINITY), "+inf");
+
+    for invalid in [
+        "", "007", "+007", "00", "0_1", "1__000", "_1", "1_", "0.", ".7", "7.e3", "3.e+20", "1e",
+        "1e_9", "0x1f", "1.5.2", "- 1", "infinity",
+    ] {
+        assert!(
+            parse_toml_like_number(invalid).is_err(),
+            "Expected {invalid} not to parse as a number"
+        );
+    }
 }
 
 #[test]
@@ -367,7 +456,7 @@ fn test_parse_scalar_list_edits_whitespace() {
             scalar_list_edit(ListEditAction::Remove, [42.0]),
             scalar_list_edit(ListEditAction::Add, [-127.1, 0.0]),
         ],
-        f64::parse_list(" - [ 42.0 , ] , + [ -127.1  ,0. ] ")
+        f64::parse_list(" - [ 42.0 , ] , + [ -127.1  ,0.0 ] ")
     );
 }
 
@@ -688,3 +777,40 @@ fn test_parse_heterogeneous_dict() {
         )
     );
 }
+
+#[test]
+fn test_parse_as_toml_scalar() {
+    use toml::Value;
+
+    let string = |s: &str| Value::String(s.to_owned());
+    let datetime = |s: &str| Value::Datetime(s.parse().unwrap());
+
+    assert_eq!(Value::Boolean(true), parse_as_toml_scalar("true"));
+    assert_eq!(Value::Boolean(false), parse_as_toml_scalar("false"));
+    assert_eq!(Value::Integer(42), parse_as_toml_scalar("42"));
+    assert_eq!(Value::Integer(-1_000), parse_as_toml_scalar("-1_000"));
+    assert_eq!(Value::Integer(42), parse_as_toml_scalar("0x2a"));
+    assert_eq!(Value::Float(1.5), parse_as_toml_scalar("1.5"));
+    assert_eq!(Value::Float(2e10), parse_as_toml_scalar("2e10"));
+    assert_eq!(Value::Float(f64::INFINITY), parse_as_toml_scalar("inf"));
+    assert_eq!(datetime("1979-05-27"), parse_as_toml_scalar("1979-05-27"));
+    assert_eq!(
+        datetime("1979-05-27T07:32:00Z"),
+        parse_as_toml_scalar("1979-05-27T07:32:00Z")
+    );
+    assert_eq!(datetime("07:32:00"), parse_as_toml_scalar("07:32:00"));
+
+    // Anything that isn't a TOML scalar stays a string.
+    assert_eq!(string("hello"), parse_as_toml_scalar("hello"));
+    assert_eq!(string(""), parse_as_toml_scalar(""));
+    assert_eq!(string("True"), parse_as_toml_scalar("True"));
+    assert_eq!(string("007"), parse_as_toml_scalar("007"));
+    assert_eq!(string("[1, 2]"), parse_as_toml_scalar("[1, 2]"));
+    assert_eq!(string("{ a = 1 }"), parse_as_toml_scalar("{ a = 1 }"));
+    assert_eq!(string("1 # one"), parse_as_toml_scalar("1 # one"));
+    assert_eq!(string("1\nother = 2"), parse_as_toml_scalar("1\nother = 2"));
+
+    // Quoting forces a string.
+    assert_eq!(string("true"), parse_as_toml_scalar("\"true\""));
+    assert_eq!(string("42"), parse_as_toml_scalar("'42'"));
+}
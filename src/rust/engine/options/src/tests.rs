@@ -3,13 +3,15 @@
 
 use crate::config::ConfigSource;
 use crate::{
-    option_id, Args, BuildRoot, DictEdit, DictEditAction, Env, ListEdit, ListEditAction,
-    OptionParser, Source, Val,
+    option_id, AppliedListEdit, Args, BuildRoot, Dedup, DictEdit, DictEditAction, Env, ListEdit,
+    ListEditAction, OptionId, OptionParser, OptionSpec, OptionsSource, Source, Val,
 };
 use maplit::hashmap;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tempfile::TempDir;
 
 fn config_source() -> Source {
@@ -212,6 +214,13 @@ fn test_parse_list_options() {
         }
     }
 
+    fn clear() -> ListEdit<i64> {
+        ListEdit {
+            action: ListEditAction::Clear,
+            items: vec![],
+        }
+    }
+
     check(
         vec![0, 1, 2, 3, 4, 5, 6, 7],
         vec![
@@ -390,6 +399,21 @@ fn test_parse_list_options() {
         "[scope]\nfoo.remove = [0]",
         "",
     );
+
+    // Clearing discards all prior edits, and later edits apply on top.
+    check(
+        vec![3, 4],
+        vec![
+            (Source::Default, vec![replace(vec![0])]),
+            (config_source(), vec![add(vec![1, 2]), remove(vec![0])]),
+            (extra_config_source(), vec![clear(), add(vec![3])]),
+            (Source::Env, vec![add(vec![4])]),
+        ],
+        vec![],
+        vec![("PANTS_SCOPE_FOO", "+[4]")],
+        "[scope]\nfoo.add = [1, 2]\nfoo.remove = [0]",
+        "[scope]\nfoo.clear = true\nfoo.add = [3]",
+    );
 }
 
 #[test]
@@ -555,3 +579,373 @@ fn test_parse_dict_options() {
         "",
     );
 }
+
+#[test]
+fn test_resolve() {
+    with_setup(
+        vec!["--scope-flag"],
+        vec![("PANTS_SCOPE_NAME", "env")],
+        "[scope]\n\
+         level = 3\n\
+         ratio = 0.5\n\
+         names.add = ['config']\n\
+         env = { add = { FOO = 'bar' } }\n",
+        "",
+        |option_parser| {
+            // With user values.
+            assert!(option_parser
+                .resolve(&option_id!(["scope"], "flag"), &OptionSpec::new(false))
+                .unwrap());
+            assert_eq!(
+                3,
+                option_parser
+                    .resolve(&option_id!(["scope"], "level"), &OptionSpec::<i64>::new(1))
+                    .unwrap()
+            );
+            assert_eq!(
+                Some(0.5),
+                option_parser
+                    .resolve(&option_id!(["scope"], "ratio"), &OptionSpec::new(None))
+                    .unwrap()
+            );
+            assert_eq!(
+                "env",
+                option_parser
+                    .resolve(
+                        &option_id!(["scope"], "name"),
+                        &OptionSpec::new("default".to_string())
+                    )
+                    .unwrap()
+            );
+            assert_eq!(
+                vec!["default".to_string(), "config".to_string()],
+                option_parser
+                    .resolve(
+                        &option_id!(["scope"], "names"),
+                        &OptionSpec::new(vec!["default".to_string()])
+                    )
+                    .unwrap()
+            );
+            assert_eq!(
+                hashmap! {
+                    "BAZ".to_string() => Val::String("qux".to_string()),
+                    "FOO".to_string() => Val::String("bar".to_string()),
+                },
+                option_parser
+                    .resolve(
+                        &option_id!(["scope"], "env"),
+                        &OptionSpec::new(hashmap! {
+                            "BAZ".to_string() => Val::String("qux".to_string()),
+                        })
+                    )
+                    .unwrap()
+            );
+
+            // Without user values.
+            assert_eq!(
+                None,
+                option_parser
+                    .resolve(
+                        &option_id!(["scope"], "missing"),
+                        &OptionSpec::<Option<String>>::new(None)
+                    )
+                    .unwrap()
+            );
+            assert_eq!(
+                vec![1, 2],
+                option_parser
+                    .resolve(
+                        &option_id!(["scope"], "missing"),
+                        &OptionSpec::<Vec<i64>>::new(vec![1, 2])
+                    )
+                    .unwrap()
+            );
+            assert!(option_parser
+                .resolve(
+                    &option_id!(["scope"], "missing"),
+                    &OptionSpec::new(HashMap::new())
+                )
+                .unwrap()
+                .is_empty());
+        },
+    );
+}
+
+#[test]
+fn test_resolve_string_list_trace() {
+    with_setup(
+        vec!["--scope-names=-['x']"],
+        vec![("PANTS_SCOPE_NAMES", "+['c']")],
+        "[scope]\nnames = ['a', 'b']",
+        "[scope]\nnames = { clear = true, add = ['x'] }",
+        |option_parser| {
+            let (list_value, trace) = option_parser
+                .resolve_string_list(&option_id!(["scope"], "names"), vec!["default".to_string()])
+                .unwrap();
+            assert_eq!(vec!["c".to_string()], list_value.value);
+
+            fn applied(
+                source: Source,
+                action: ListEditAction,
+                items: &[&str],
+            ) -> AppliedListEdit<String> {
+                AppliedListEdit {
+                    source,
+                    action,
+                    items: items.iter().map(|item| item.to_string()).collect(),
+                }
+            }
+            assert_eq!(
+                vec![
+                    applied(Source::Default, ListEditAction::Replace, &["default"]),
+                    applied(config_source(), ListEditAction::Replace, &["a", "b"]),
+                    applied(extra_config_source(), ListEditAction::Clear, &[]),
+                    applied(extra_config_source(), ListEditAction::Add, &["x"]),
+                    applied(Source::Env, ListEditAction::Add, &["c"]),
+                    applied(Source::Flag, ListEditAction::Remove, &["x"]),
+                ],
+                trace
+            );
+        },
+    );
+}
+
+#[test]
+fn test_resolve_string_list_reads_sources_once() {
+    // A source whose string list value changes each time that it is read, as the value of a
+    // `from_command` might.
+    struct ChangingSource {
+        reads: AtomicUsize,
+    }
+
+    impl OptionsSource for ChangingSource {
+        fn display(&self, id: &OptionId) -> String {
+            id.to_string()
+        }
+
+        fn get_string(&self, _id: &OptionId) -> Result<Option<String>, String> {
+            unimplemented!()
+        }
+
+        fn get_bool(&self, _id: &OptionId) -> Result<Option<bool>, String> {
+            unimplemented!()
+        }
+
+        fn get_bool_list(&self, _id: &OptionId) -> Result<Option<Vec<ListEdit<bool>>>, String> {
+            unimplemented!()
+        }
+
+        fn get_int_list(&self, _id: &OptionId) -> Result<Option<Vec<ListEdit<i64>>>, String> {
+            unimplemented!()
+        }
+
+        fn get_float_list(&self, _id: &OptionId) -> Result<Option<Vec<ListEdit<f64>>>, String> {
+            unimplemented!()
+        }
+
+        fn get_string_list(&self, _id: &OptionId) -> Result<Option<Vec<ListEdit<String>>>, String> {
+            let read = self.reads.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(vec![ListEdit {
+                action: ListEditAction::Add,
+                items: vec![format!("read{read}")],
+            }]))
+        }
+
+        fn get_dict(&self, _id: &OptionId) -> Result<Option<Vec<DictEdit>>, String> {
+            unimplemented!()
+        }
+    }
+
+    let source = Arc::new(ChangingSource {
+        reads: AtomicUsize::new(0),
+    });
+    let option_parser = OptionParser {
+        sources: BTreeMap::from([(Source::Flag, source.clone() as Arc<dyn OptionsSource>)]),
+        include_derivation: true,
+        passthrough_args: None,
+    };
+
+    let (list_value, trace) = option_parser
+        .resolve_string_list(&option_id!("names"), vec![])
+        .unwrap();
+    assert_eq!(1, source.reads.load(Ordering::SeqCst));
+    assert_eq!(vec!["read0".to_string()], list_value.value);
+    assert_eq!(
+        vec![
+            AppliedListEdit {
+                source: Source::Default,
+                action: ListEditAction::Replace,
+                items: vec![],
+            },
+            AppliedListEdit {
+                source: Source::Flag,
+                action: ListEditAction::Add,
+                items: vec!["read0".to_string()],
+            },
+        ],
+        trace
+    );
+    // The derivation is of the same read.
+    assert_eq!(
+        Some(vec![
+            (
+                Source::Default,
+                vec![ListEdit {
+                    action: ListEditAction::Replace,
+                    items: vec![],
+                }]
+            ),
+            (
+                Source::Flag,
+                vec![ListEdit {
+                    action: ListEditAction::Add,
+                    items: vec!["read0".to_string()],
+                }]
+            ),
+        ]),
+        list_value.derivation
+    );
+}
+
+#[test]
+fn test_resolve_string_list_order() {
+    let buildroot = TempDir::new().unwrap();
+    let config_source = |file_name: &str, content: &str| ConfigSource {
+        path: buildroot.path().join(file_name),
+        content: content.to_string(),
+    };
+    let option_parser = OptionParser::new(
+        Args::new(Vec::<String>::new()),
+        Env {
+            env: HashMap::new(),
+        },
+        Some(vec![
+            config_source(
+                "pants.toml",
+                "[DEFAULT]\n\
+                 paths = ['base_default']\n\
+                 [scope]\n\
+                 paths.add = ['base1', 'base2', 'shared']\n",
+            ),
+            config_source(
+                "pants.ci.toml",
+                "[scope]\npaths = { add = ['ci1', 'ci2'], remove = ['base1'] }\n",
+            ),
+            config_source(
+                "pants.local.toml",
+                "[DEFAULT]\n\
+                 paths.add = ['local_default']\n\
+                 [scope]\n\
+                 paths.add = ['local1', 'base1', 'shared']\n",
+            ),
+        ]),
+        false,
+        false,
+        Some(BuildRoot::for_path(buildroot.path().to_path_buf())),
+    )
+    .unwrap();
+
+    let (list_value, _) = option_parser
+        .resolve_string_list(&option_id!(["scope"], "paths"), vec!["default".to_string()])
+        .unwrap();
+    assert_eq!(
+        vec![
+            "base_default",
+            "base2",
+            "shared",
+            "ci1",
+            "ci2",
+            "local_default",
+            "local1",
+            "shared"
+        ],
+        list_value.value
+    );
+}
+
+#[test]
+fn test_parse_flag_set() {
+    let known_flags = ["color", "fast", "safe", "verbose"];
+    with_setup(
+        vec![],
+        vec![("PANTS_SCOPE_FEATURES", "-color, verbose")],
+        "[scope]\nfeatures = 'fast,-safe'\nbad = 'fast,bogus'\n",
+        "",
+        |option_parser| {
+            assert_eq!(
+                HashSet::from(["fast".to_string(), "verbose".to_string()]),
+                option_parser
+                    .parse_flag_set(
+                        &option_id!(["scope"], "features"),
+                        &["safe", "color"],
+                        &known_flags
+                    )
+                    .unwrap()
+            );
+            assert_eq!(
+                HashSet::from(["safe".to_string()]),
+                option_parser
+                    .parse_flag_set(&option_id!(["scope"], "missing"), &["safe"], &known_flags)
+                    .unwrap()
+            );
+            assert_eq!(
+                "Unknown flag `bogus` for [scope] bad: expected color, fast, safe or verbose",
+                option_parser
+                    .parse_flag_set(&option_id!(["scope"], "bad"), &[], &known_flags)
+                    .unwrap_err()
+            );
+        },
+    );
+}
+
+#[test]
+fn test_parse_string_list_strict() {
+    with_setup(
+        vec!["--scope-tags=-['b']"],
+        vec![("PANTS_SCOPE_TAGS", "+['c', 'a']")],
+        "[scope]\ntags = \"+['a', 'b', 'a']\"\ndangling = \"-['x']\"\n",
+        "",
+        |option_parser| {
+            assert_eq!(
+                vec!["a".to_string(), "c".to_string()],
+                option_parser
+                    .parse_string_list_strict(
+                        &option_id!(["scope"], "tags"),
+                        vec![],
+                        Dedup::FirstWins
+                    )
+                    .unwrap()
+                    .value
+            );
+            assert_eq!(
+                vec!["c".to_string(), "a".to_string()],
+                option_parser
+                    .parse_string_list_strict(
+                        &option_id!(["scope"], "tags"),
+                        vec![],
+                        Dedup::LastWins
+                    )
+                    .unwrap()
+                    .value
+            );
+            assert_eq!(
+                "Expected to remove 'x' from [scope] dangling but it is not present",
+                option_parser
+                    .parse_string_list_strict(
+                        &option_id!(["scope"], "dangling"),
+                        vec!["y".to_string()],
+                        Dedup::FirstWins
+                    )
+                    .unwrap_err()
+            );
+            // The lenient default ignores the dangling removal.
+            assert_eq!(
+                vec!["y".to_string()],
+                option_parser
+                    .parse_string_list(&option_id!(["scope"], "dangling"), vec!["y".to_string()])
+                    .unwrap()
+                    .value
+            );
+        },
+    );
+}
@@ -1,6 +1,10 @@
 // Copyright 2023 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashMap;
+
+use crate::{OptionId, OptionParser, Val};
+
 /// A primitive enum for option types, containing the default value for the option.
 ///
 /// Currently only used for `pantsd` fingerprinting, which is defined in Rust. Options in general
@@ -49,3 +53,101 @@ impl From<Vec<&str>> for OptionType {
         OptionType::StringList(v.into_iter().map(|s| s.to_owned()).collect())
     }
 }
+
+/// The type and default value of an option, for resolving it via `OptionParser::resolve`.
+///
+/// The type of the option is that of its default: for example an `OptionSpec<Vec<String>>` is
+/// resolved as a string list option, with edits from each source applied on top of the default,
+/// and an `OptionSpec<Option<i64>>` is resolved as an int option that has no value by default.
+pub struct OptionSpec<T> {
+    pub default: T,
+}
+
+impl<T: Resolvable> OptionSpec<T> {
+    pub fn new(default: T) -> Self {
+        OptionSpec { default }
+    }
+}
+
+/// A type that an option value may be resolved as, via the `OptionParser` method for that type.
+pub trait Resolvable: Sized {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String>;
+}
+
+impl Resolvable for bool {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_bool(id, *default)?.value)
+    }
+}
+
+impl Resolvable for i64 {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_int(id, *default)?.value)
+    }
+}
+
+impl Resolvable for f64 {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_float(id, *default)?.value)
+    }
+}
+
+impl Resolvable for String {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_string(id, default)?.value)
+    }
+}
+
+impl Resolvable for Option<bool> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_bool_optional(id, *default)?.value)
+    }
+}
+
+impl Resolvable for Option<i64> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_int_optional(id, *default)?.value)
+    }
+}
+
+impl Resolvable for Option<f64> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_float_optional(id, *default)?.value)
+    }
+}
+
+impl Resolvable for Option<String> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_string_optional(id, default.as_deref())?.value)
+    }
+}
+
+impl Resolvable for Vec<bool> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_bool_list(id, default.clone())?.value)
+    }
+}
+
+impl Resolvable for Vec<i64> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_int_list(id, default.clone())?.value)
+    }
+}
+
+impl Resolvable for Vec<f64> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_float_list(id, default.clone())?.value)
+    }
+}
+
+impl Resolvable for Vec<String> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_string_list(id, default.clone())?.value)
+    }
+}
+
+impl Resolvable for HashMap<String, Val> {
+    fn resolve(parser: &OptionParser, id: &OptionId, default: &Self) -> Result<Self, String> {
+        Ok(parser.parse_dict(id, default.clone())?.value)
+    }
+}